@@ -17,12 +17,20 @@ pub struct Nnue {
     hidden_layer: A<A<i8>>,
     #[serde(rename = "out.bias")]
     hidden_layer_bias: A<i32>,
+    /// Recorded by newer trainers; older nets were all trained with squared clipped ReLU.
+    #[serde(default)]
+    activation: Option<String>,
 }
 
 fn main() {
     println!("cargo:rerun-if-changed=build.rs");
     println!("cargo:rerun-if-env-changed=EVALFILE");
 
+    let activation = match std::env::var_os("CARGO_FEATURE_CRELU") {
+        Some(_) => "crelu",
+        None => "screlu",
+    };
+
     let eval_file = std::env::var_os("EVALFILE");
     let eval_file: &Path = eval_file
         .as_ref()
@@ -39,9 +47,20 @@ fn main() {
     )
     .unwrap();
 
+    if let Some(trained) = &model.activation {
+        if trained != activation {
+            panic!(
+                "the network was trained with {} activation, but the engine is built for {}; \
+                 toggle the crelu feature to match",
+                trained, activation
+            );
+        }
+    }
+
     let out_dir: PathBuf = std::env::var_os("OUT_DIR").unwrap().into();
     let mut output = BufWriter::new(File::create(out_dir.join("model.rs")).unwrap());
 
+    writeln!(output, "// activation: {}", activation).unwrap();
     writeln!(output, "{}", model).unwrap();
 }
 
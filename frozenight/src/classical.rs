@@ -0,0 +1,93 @@
+//! A small hand-crafted evaluation that doesn't depend on the network.
+
+use cozy_chess::{Board, Color, Piece, Rank, Square};
+
+use crate::{game_phase, Eval};
+
+/// Middlegame and endgame piece values in centipawns, in the order of [`Piece::ALL`].
+const MATERIAL: [(i32, i32); Piece::NUM] = [
+    (82, 94),
+    (337, 281),
+    (365, 297),
+    (477, 512),
+    (1025, 936),
+    (0, 0),
+];
+
+/// A tapered material and piece-square evaluation of `board` for the side to move, used as a
+/// reference when debugging the network and in its place with the `no-nnue` feature.
+pub fn classical_eval(board: &Board) -> Eval {
+    let mut mg = 0;
+    let mut eg = 0;
+    for color in [Color::White, Color::Black] {
+        let sign = match color {
+            Color::White => 1,
+            Color::Black => -1,
+        };
+        for piece in Piece::ALL {
+            for sq in board.pieces(piece) & board.colors(color) {
+                let (piece_mg, piece_eg) = piece_square(piece, relative_square(sq, color));
+                mg += sign * (MATERIAL[piece as usize].0 + piece_mg);
+                eg += sign * (MATERIAL[piece as usize].1 + piece_eg);
+            }
+        }
+    }
+
+    let phase = game_phase(board) as i32;
+    let cp = (mg * (256 - phase) + eg * phase) / 256;
+    let eval = (cp * Eval::CP_SCALE as i32).clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+    match board.side_to_move() {
+        Color::White => Eval::new(eval),
+        Color::Black => -Eval::new(eval),
+    }
+}
+
+/// The middlegame and endgame bonus for `piece` on `sq`, seen from white's side of the board.
+fn piece_square(piece: Piece, sq: Square) -> (i32, i32) {
+    let rank = sq.rank() as i32;
+    let file = sq.file() as i32;
+    // 0 in the corners up to 6 in the four center squares
+    let center = file.min(7 - file) + rank.min(7 - rank);
+    match piece {
+        Piece::Pawn => (5 * (rank - 1) + 5 * file.min(7 - file), 12 * (rank - 1)),
+        Piece::Knight => (8 * center - 24, 6 * center - 18),
+        Piece::Bishop => (4 * center - 12, 3 * center - 9),
+        Piece::Rook => match sq.rank() == Rank::Seventh {
+            true => (20, 10),
+            false => (0, 0),
+        },
+        Piece::Queen => (2 * center - 6, 4 * center - 12),
+        // shelter on the back rank while there is material to attack the king, but centralize
+        // it in the endgame
+        Piece::King => (-10 * center - 15 * rank, 10 * center - 30),
+    }
+}
+
+fn relative_square(sq: Square, color: Color) -> Square {
+    match color {
+        Color::White => sq,
+        Color::Black => sq.flip_rank(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn eval_is_symmetric() {
+        assert_eq!(classical_eval(&Board::default()), Eval::DRAW);
+
+        // swapping the colors of every piece negates the eval, and also swapping the side to move
+        // gives the same eval back
+        let board: Board = "r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4"
+            .parse()
+            .unwrap();
+        let flipped = "rnbqk2r/pppp1ppp/5n2/2b1p3/4P3/2N2N2/PPPP1PPP/R1BQKB1R";
+        let white: Board = format!("{} w KQkq - 4 4", flipped).parse().unwrap();
+        let black: Board = format!("{} b KQkq - 4 4", flipped).parse().unwrap();
+        assert_ne!(classical_eval(&board), Eval::DRAW);
+        assert_eq!(classical_eval(&white), -classical_eval(&board));
+        assert_eq!(classical_eval(&black), classical_eval(&board));
+    }
+}
@@ -1,4 +1,5 @@
 use bytemuck::{Pod, Zeroable};
+use cozy_chess::{Board, Color, Piece};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Pod, Zeroable, Default)]
 #[repr(transparent)]
@@ -10,6 +11,9 @@ impl Eval {
     pub const MAX_INCONCLUSIVE: Eval = Eval(200_00);
     pub const DRAW: Eval = Eval(0);
 
+    /// Number of internal eval units per centipawn, e.g. an internal eval of 250 is 50 cp.
+    pub const CP_SCALE: i16 = 5;
+
     pub fn new(value: i16) -> Self {
         Eval(value).clamp(-Eval::MAX_INCONCLUSIVE, Eval::MAX_INCONCLUSIVE)
     }
@@ -47,31 +51,89 @@ impl Eval {
 
     /// If this eval is conclusive, decreases the score by the indicated number of plys.
     pub fn add_time(self, plys: u16) -> Self {
-        if self < -Self::MAX_INCONCLUSIVE {
-            Eval(self.0 + plys as i16)
-        } else if self > Self::MAX_INCONCLUSIVE {
-            Eval(self.0 - plys as i16)
-        } else {
-            self
-        }
+        self.adjust_conclusive(-(plys as i32))
     }
 
     /// If this eval is conclusive, increases the score by the indicated number of plys.
     pub fn sub_time(self, plys: u16) -> Self {
-        if self < -Self::MAX_INCONCLUSIVE {
-            debug_assert!(self.0 - plys as i16 > -Eval::MATE.0);
-            Eval(self.0 - plys as i16)
-        } else if self > Self::MAX_INCONCLUSIVE {
-            debug_assert!(self.0 + (plys as i16) < Eval::MATE.0);
-            Eval(self.0 + plys as i16)
-        } else {
-            self
-        }
+        self.adjust_conclusive(plys as i32)
+    }
+
+    /// Moves a conclusive eval `delta` further from zero, clamped to the range of its kind.
+    fn adjust_conclusive(self, delta: i32) -> Self {
+        let magnitude = self.0.abs();
+        let (min, max) = match () {
+            _ if magnitude > Self::TB_WIN.0 => (Self::TB_WIN.0 + 1, Self::MATE.0),
+            _ if magnitude > Self::MAX_INCONCLUSIVE.0 => {
+                (Self::MAX_INCONCLUSIVE.0 + 1, Self::TB_WIN.0)
+            }
+            _ => return self,
+        };
+        let magnitude = (magnitude as i32 + delta).clamp(min as i32, max as i32) as i16;
+        Eval(magnitude * self.0.signum())
     }
 
     pub fn raw(self) -> i16 {
         self.0
     }
+
+    /// Converts the eval from internal units to centipawns.
+    pub fn as_centipawns(self) -> i16 {
+        self.0 / Self::CP_SCALE
+    }
+}
+
+/// The material on the board using pawn = 1, minor = 3, rook = 5, queen = 8, counting both sides.
+/// This is 76 in the starting position, but promotions can raise it further.
+pub fn material_count(board: &Board) -> usize {
+    board.pieces(Piece::Pawn).len() as usize
+        + 3 * board.pieces(Piece::Bishop).len() as usize
+        + 3 * board.pieces(Piece::Knight).len() as usize
+        + 5 * board.pieces(Piece::Rook).len() as usize
+        + 8 * board.pieces(Piece::Queen).len() as usize
+}
+
+/// How far the game has progressed towards the endgame based on the material left, from 0 with
+/// the material of the starting position (or more) to 256 with only kings.
+pub fn game_phase(board: &Board) -> u32 {
+    256 - material_count(board).min(76) as u32 * 256 / 76
+}
+
+/// Converts an eval to centipawns scaled by the [`material_count`] so that 100 is a 50% chance of
+/// winning in any phase. Conclusive evals are returned in plain centipawns.
+pub fn normalize_eval(eval: Eval, material: usize) -> i16 {
+    if eval.is_conclusive() {
+        return eval.as_centipawns();
+    }
+    let (a, _) = wdl_model(material);
+    (eval.0 as f64 * 100.0 / a).round() as i16
+}
+
+/// The probability that the side to move wins with `eval` at the given material count.
+pub fn win_probability(eval: Eval, material: usize) -> f64 {
+    let (a, b) = wdl_model(material);
+    1.0 / (1.0 + ((a - eval.0 as f64) / b).exp())
+}
+
+/// The parameters `(a, b)` of the win probability model at `material`: an eval of `a` internal
+/// units wins half the time, and `b` is how far the eval has to move to change the odds by a
+/// factor of e.
+///
+/// These are an interim choice that keeps the normalized scale close to the one used before the
+/// model existed; they have not been fitted to game results.
+fn wdl_model(material: usize) -> (f64, f64) {
+    let material = material.min(76) as f64;
+    let a = 400.0 + 2.0 * material;
+    let b = 100.0 + 1.5 * material;
+    (a, b)
+}
+
+/// Converts an eval relative to the side to move `stm` into one relative to white.
+pub fn white_relative(eval: Eval, stm: Color) -> Eval {
+    match stm {
+        Color::White => eval,
+        Color::Black => -eval,
+    }
 }
 
 impl std::ops::Neg for Eval {
@@ -98,11 +160,107 @@ impl std::ops::Sub<i16> for Eval {
     }
 }
 
+/// Formats the eval as a UCI score, with mates counted in full moves.
 impl std::fmt::Display for Eval {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self.plys_to_conclusion() {
+            // round away from zero so an odd number of plys counts our final move
             Some(plys) => write!(f, "mate {}", (plys + plys.signum()) / 2),
-            None => write!(f, "cp {}", self.0 / 5),
+            None => write!(f, "cp {}", self.as_centipawns()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn time_adjustments_saturate_within_their_kind() {
+        assert!(Eval::MATE.add_time(u16::MAX) > Eval::TB_WIN);
+        assert!((-Eval::MATE).add_time(u16::MAX) < -Eval::TB_WIN);
+        assert!(Eval::TB_WIN.add_time(u16::MAX).is_conclusive());
+        assert!(Eval::TB_WIN.add_time(u16::MAX) < Eval::TB_WIN);
+        assert_eq!(Eval::new(100).add_time(u16::MAX), Eval::new(100));
+
+        assert_eq!(Eval::MATE.add_time(7).sub_time(7), Eval::MATE);
+        assert_eq!(Eval::MATE.add_time(10).sub_time(u16::MAX), Eval::MATE);
+        assert_eq!((-Eval::MATE).add_time(10).sub_time(u16::MAX), -Eval::MATE);
+        assert_eq!(Eval::TB_WIN.add_time(10).sub_time(u16::MAX), Eval::TB_WIN);
+    }
+
+    #[test]
+    fn centipawns_round_towards_zero() {
+        assert_eq!(Eval::new(250).as_centipawns(), 50);
+        assert_eq!(Eval::new(-250).as_centipawns(), -50);
+        assert_eq!(Eval::new(4).as_centipawns(), 0);
+        assert_eq!(Eval::DRAW.as_centipawns(), 0);
+    }
+
+    #[test]
+    fn material_and_phase() {
+        let bare: Board = "4k3/8/8/8/8/8/8/4K3 w - - 0 1".parse().unwrap();
+        let promoted: Board = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNQ w kq - 0 1"
+            .parse()
+            .unwrap();
+        assert_eq!(material_count(&Board::default()), 76);
+        assert_eq!(material_count(&bare), 0);
+        assert_eq!(material_count(&promoted), 79);
+        assert_eq!(game_phase(&Board::default()), 0);
+        assert_eq!(game_phase(&promoted), 0);
+        assert_eq!(game_phase(&bare), 256);
+    }
+
+    #[test]
+    fn normalized_100_is_an_even_chance_of_winning() {
+        assert_eq!(normalize_eval(Eval::DRAW, 76), 0);
+        assert_eq!(normalize_eval(Eval::DRAW, 0), 0);
+        assert!(normalize_eval(Eval::new(500), 76) > 0);
+        assert!(normalize_eval(Eval::new(-500), 0) < 0);
+
+        for material in [0, 20, 40, 76] {
+            let eval = (1..2000)
+                .map(Eval::new)
+                .find(|&e| normalize_eval(e, material) >= 100)
+                .unwrap();
+            assert!((win_probability(eval, material) - 0.5).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn win_probability_rises_with_eval_and_falls_with_material() {
+        assert!(win_probability(Eval::DRAW, 76) < 0.1);
+        assert!(win_probability(Eval::new(5000), 76) > 0.9);
+        assert!(win_probability(Eval::new(500), 10) > win_probability(Eval::new(500), 76));
+        for material in [0, 40, 76] {
+            let probs: Vec<_> = (-10..=10)
+                .map(|e| win_probability(Eval::new(e * 200), material))
+                .collect();
+            assert!(probs.windows(2).all(|w| w[0] < w[1]));
+        }
+    }
+
+    #[test]
+    fn white_relative_flips_for_black() {
+        assert_eq!(white_relative(Eval::new(250), Color::White), Eval::new(250));
+        assert_eq!(
+            white_relative(Eval::new(250), Color::Black),
+            Eval::new(-250)
+        );
+        let mated = (-Eval::MATE).add_time(3);
+        assert_eq!(white_relative(mated, Color::Black).to_string(), "mate 2");
+    }
+
+    #[test]
+    fn mates_display_in_full_moves() {
+        let winning = [0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5];
+        let losing = [0, -1, -1, -2, -2, -3, -3, -4, -4, -5, -5];
+        for k in 0..=10 {
+            let win = Eval::MATE.add_time(k);
+            let loss = (-Eval::MATE).add_time(k);
+            assert_eq!(win.to_string(), format!("mate {}", winning[k as usize]));
+            assert_eq!(loss.to_string(), format!("mate {}", losing[k as usize]));
         }
+        assert_eq!(Eval::new(250).to_string(), "cp 50");
     }
 }
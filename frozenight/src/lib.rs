@@ -4,25 +4,36 @@ use std::sync::atomic::{AtomicBool, AtomicI16, AtomicU64, Ordering};
 use std::sync::{Arc, RwLock};
 use std::time::Instant;
 
-use cozy_chess::{Board, Move};
+use cozy_chess::{Board, GameStatus, Move};
 
+mod classical;
 mod eval;
+mod keys;
 mod nnue;
 mod position;
+mod san;
 mod search;
 mod threading;
 mod time;
 mod tt;
+mod uci_move;
 
-pub use eval::Eval;
-pub use threading::MtFrozenight;
-pub use time::TimeConstraint;
+pub use classical::classical_eval;
+pub use eval::{game_phase, material_count, normalize_eval, white_relative, win_probability, Eval};
+pub use keys::{polyglot_key, position_key};
+pub use nnue::static_eval_board;
+pub use san::move_to_san;
+pub use threading::{AbortHandle, MtFrozenight, SearchListener, TrajectoryPoint};
+pub use time::{MovesToGo, TimeConstraint};
+pub use uci_move::parse_move;
 
-use search::{PrivateState, Searcher, INVALID_MOVE};
+use search::{PrivateState, Searcher, SharedHistory, MAX_DEPTH};
 use time::TimeManager;
 use tt::TranspositionTable;
 
-pub use search::all_parameters;
+#[cfg(feature = "tweakable")]
+pub use search::dump_spsa_config;
+pub use search::{all_parameters, is_dead_draw, INVALID_MOVE};
 
 pub struct Frozenight {
     board: Board,
@@ -30,43 +41,90 @@ pub struct Frozenight {
     shared_state: Arc<RwLock<SharedState>>,
     stats: Arc<Statistics>,
     state: PrivateState,
+    excluded: Vec<Move>,
 }
 
 #[derive(Clone, Debug)]
 pub struct SearchInfo {
     pub eval: Eval,
+    /// The nodes searched by all threads together.
     pub nodes: u64,
+    /// The nodes searched by each thread, which add up to `nodes`.
+    pub thread_nodes: Vec<u64>,
+    /// The depth of the completed iteration, reported once after any aspiration re-searches.
     pub depth: i16,
+    /// The deepest ply reached by the search, including quiescence search.
     pub selective_depth: i16,
+    /// The deepest point reached by a PV node, including extensions along the PV.
+    pub effective_depth: i16,
     pub hashfull: usize,
+    /// The best move found, or [`INVALID_MOVE`] if the root position has no legal moves.
     pub best_move: Move,
     pub pv: Vec<Move>,
+    pub aspiration: AspirationStats,
+    /// Why the search stopped, or `None` for reports made while the search is running.
+    pub stop_reason: Option<StopReason>,
+    /// For each other root move, the line expected to refute it. See
+    /// [`MtFrozenight::set_refutations`].
+    pub refutations: Vec<(Move, Vec<Move>)>,
+    /// Whether playing `best_move` lets the engine claim a draw.
+    pub claimable_draw: bool,
+}
+
+/// The reason a search stopped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    /// The maximum depth was searched, or the root position has no legal moves.
+    Depth,
+    /// The node limit was reached.
+    Nodes,
+    /// The time manager ended the search, or the deadline passed.
+    Time,
+    /// The search was aborted from outside.
+    Abort,
+}
+
+/// Counts of aspiration window failures over the course of a search.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AspirationStats {
+    pub fail_highs: u64,
+    pub fail_lows: u64,
+    pub researches: u64,
 }
 
 #[derive(Debug, Default)]
 struct Statistics {
     selective_depth: AtomicI16,
+    effective_depth: AtomicI16,
     nodes: AtomicU64,
+    aspiration_fail_highs: AtomicU64,
+    aspiration_fail_lows: AtomicU64,
+    aspiration_researches: AtomicU64,
 }
 
 struct SharedState {
     tt: TranspositionTable,
+    history: SharedHistory,
 }
 
 impl Frozenight {
     pub fn new(hash_mb: usize) -> Self {
         Self::create(Arc::new(RwLock::new(SharedState {
             tt: TranspositionTable::new(hash_mb),
+            history: Default::default(),
         })))
     }
 
     fn create(shared_state: Arc<RwLock<SharedState>>) -> Self {
+        let board = Board::default();
         Frozenight {
-            board: Default::default(),
-            prehistory: vec![],
+            // the root must always be in the repetition history, even before a position is set
+            prehistory: vec![board.hash()],
+            board,
             shared_state,
             stats: Default::default(),
             state: Default::default(),
+            excluded: vec![],
         }
     }
 
@@ -74,20 +132,24 @@ impl Frozenight {
         &self.board
     }
 
+    /// Forgets the history of previous searches and returns to the start position.
     pub fn new_game(&mut self) {
-        self.state = Default::default();
-        Arc::get_mut(&mut self.shared_state)
+        self.set_position(Board::default(), std::iter::empty());
+        self.state.clear();
+        let shared = Arc::get_mut(&mut self.shared_state)
             .unwrap()
             .get_mut()
-            .unwrap()
-            .tt
-            .increment_age(2);
+            .unwrap();
+        shared.tt.increment_age(2);
+        shared.history.clear();
     }
 
+    /// Sets the position to search, given as a starting position and the moves played since.
     pub fn set_position(&mut self, position: Board, moves: impl Iterator<Item = Move>) {
         let mut new = position;
         let age_inc = update_position(&mut new, &mut self.prehistory, &self.board, moves);
         self.board = new;
+        self.excluded.clear();
         Arc::get_mut(&mut self.shared_state)
             .unwrap()
             .get_mut()
@@ -96,6 +158,13 @@ impl Frozenight {
             .increment_age(age_inc);
     }
 
+    /// Prevents the search from playing any of `moves` until the position changes, unless that
+    /// would exclude every legal move.
+    pub fn set_excluded_moves(&mut self, moves: Vec<Move>) {
+        self.excluded = moves;
+    }
+
+    /// Resizes the transposition table. Sizes below 1 MB are rounded up to 1 MB.
     pub fn set_hash(&mut self, hash_mb: usize) {
         let mut shared = Arc::get_mut(&mut self.shared_state)
             .unwrap()
@@ -106,22 +175,15 @@ impl Frozenight {
         shared.tt = TranspositionTable::new(hash_mb);
     }
 
+    /// Searches the current position, calling `info` each time an iteration completes.
     pub fn search(
         &mut self,
         time: TimeConstraint,
         mut info: impl FnMut(&SearchInfo),
     ) -> SearchInfo {
-        let mut recent_info = SearchInfo {
-            eval: Eval::DRAW,
-            nodes: 0,
-            depth: 0,
-            hashfull: 0,
-            selective_depth: 0,
-            best_move: INVALID_MOVE,
-            pv: vec![],
-        };
+        let mut recent_info = SearchInfo::unsearched(&self.board);
         let mut tm = TimeManager::new(&self.board, time);
-        self.search_internal(
+        let reason = self.search_internal(
             time.depth,
             time.nodes,
             &Default::default(),
@@ -132,15 +194,24 @@ impl Frozenight {
                     depth,
                     hashfull: searcher.shared.tt.hashfull(),
                     selective_depth: searcher.stats.selective_depth.load(Ordering::Relaxed),
+                    effective_depth: searcher.stats.effective_depth.load(Ordering::Relaxed),
                     nodes: searcher.stats.nodes.load(Ordering::Relaxed),
+                    thread_nodes: vec![searcher.stats.nodes.load(Ordering::Relaxed)],
                     best_move,
                     pv: searcher.extract_pv(depth),
+                    aspiration: searcher.stats.aspiration(),
+                    stop_reason: None,
+                    refutations: vec![],
+                    claimable_draw: searcher.claimable_draw(best_move),
                 };
                 info(&recent_info);
 
                 tm.update(&recent_info)
             },
         );
+        recent_info.stop_reason = Some(reason);
+        recent_info.nodes = self.stats.nodes.load(Ordering::Relaxed);
+        recent_info.thread_nodes = vec![recent_info.nodes];
         recent_info
     }
 
@@ -151,35 +222,118 @@ impl Frozenight {
         abort: &AtomicBool,
         deadline: Option<Instant>,
         mut depth_complete: impl FnMut(i16, &mut Searcher, Move, Eval) -> ControlFlow<()>,
-    ) {
+    ) -> StopReason {
         self.stats.clear();
 
-        self.with_searcher(max_nodes, abort, deadline, |mut searcher| {
+        if !self.board.generate_moves(|_| true) {
+            return StopReason::Depth;
+        }
+
+        // the node limit is soft and only checked between iterations, but a runaway iteration is
+        // still cut off at twice the limit
+        let hard_node_limit = max_nodes.saturating_mul(2);
+        self.with_searcher(hard_node_limit, abort, deadline, |mut searcher| {
             let mut prev_eval = Eval::DRAW;
 
-            for depth in 1..=max_depth {
+            for depth in 1..=max_depth.min(MAX_DEPTH) {
                 let (eval, mv) = match searcher.search(depth, prev_eval) {
                     Some(v) => v,
-                    None => break,
+                    None => return searcher.stop_reason.unwrap(),
                 };
 
                 if depth_complete(depth, &mut searcher, mv, eval).is_break() {
-                    break;
+                    return StopReason::Time;
+                }
+                if searcher.stats.nodes.load(Ordering::Relaxed) >= max_nodes {
+                    return StopReason::Nodes;
                 }
 
                 prev_eval = eval;
             }
+
+            StopReason::Depth
         })
     }
 }
 
+/// Searches `board` to `depth` with a fresh single-threaded engine.
+pub fn quick_search(board: &Board, depth: i16, hash_mb: usize) -> SearchInfo {
+    let mut engine = Frozenight::new(hash_mb);
+    engine.set_position(board.clone(), std::iter::empty());
+    engine.search(TimeConstraint::fixed_depth(depth), |_| {})
+}
+
+/// Like [`quick_search`], but writes the decisions made at the root to stderr.
+#[cfg(feature = "trace")]
+pub fn trace_root(board: &Board, depth: i16) -> SearchInfo {
+    search::TRACE.with(|t| t.set(true));
+    let info = quick_search(board, depth, 16);
+    search::TRACE.with(|t| t.set(false));
+    info
+}
+
+impl SearchInfo {
+    /// The info reported before any iteration of the search has completed, which is also the
+    /// final result if the root has no legal moves.
+    fn unsearched(board: &Board) -> Self {
+        let eval = match board.status() {
+            GameStatus::Won => -Eval::MATE,
+            _ => Eval::DRAW,
+        };
+        SearchInfo {
+            eval,
+            nodes: 0,
+            thread_nodes: vec![],
+            depth: 0,
+            selective_depth: 0,
+            effective_depth: 0,
+            hashfull: 0,
+            best_move: INVALID_MOVE,
+            pv: vec![],
+            aspiration: AspirationStats::default(),
+            stop_reason: None,
+            refutations: vec![],
+            claimable_draw: false,
+        }
+    }
+}
+
 impl Statistics {
     fn clear(&self) {
         self.selective_depth.store(0, Ordering::Relaxed);
+        self.effective_depth.store(0, Ordering::Relaxed);
         self.nodes.store(0, Ordering::Relaxed);
+        self.aspiration_fail_highs.store(0, Ordering::Relaxed);
+        self.aspiration_fail_lows.store(0, Ordering::Relaxed);
+        self.aspiration_researches.store(0, Ordering::Relaxed);
+    }
+
+    fn aspiration(&self) -> AspirationStats {
+        AspirationStats {
+            fail_highs: self.aspiration_fail_highs.load(Ordering::Relaxed),
+            fail_lows: self.aspiration_fail_lows.load(Ordering::Relaxed),
+            researches: self.aspiration_researches.load(Ordering::Relaxed),
+        }
     }
 }
 
+/// The root moves a search of `board` should skip: `excluded`, unless that would leave no legal
+/// moves to search.
+fn root_exclusions<'a>(board: &Board, excluded: &'a [Move]) -> &'a [Move] {
+    let mut any_allowed = false;
+    board.generate_moves(|mvs| {
+        any_allowed = mvs.into_iter().any(|mv| !excluded.contains(&mv));
+        any_allowed
+    });
+    match any_allowed {
+        true => excluded,
+        false => &[],
+    }
+}
+
+/// Plays `moves` on `board`, collecting the hashes the search should treat as repetitions into
+/// `prehistory`: positions that occurred at least twice since the last irreversible move, followed
+/// by the new root. Returns how much to age the transposition table by.
 fn update_position(
     board: &mut Board,
     prehistory: &mut Vec<u64>,
@@ -219,3 +373,201 @@ fn update_position(
         _ => 2,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn engine_at(fen: &str) -> Frozenight {
+        let mut engine = Frozenight::new(1);
+        engine.set_position(fen.parse().unwrap(), std::iter::empty());
+        engine
+    }
+
+    #[test]
+    fn each_depth_is_reported_once() {
+        let mut engine = Frozenight::new(1);
+        let mut depths = vec![];
+        engine.search(TimeConstraint::fixed_depth(8), |info| {
+            depths.push(info.depth)
+        });
+        assert_eq!(depths, (1..=8).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn selective_depth_includes_qsearch() {
+        let mut engine = engine_at("rnbqkbnr/ppp1pppp/8/3p4/4P3/8/PPPP1PPP/RNBQKBNR w KQkq d6 0 2");
+        let info = engine.search(TimeConstraint::fixed_depth(1), |_| {});
+        // exd5 Qxd5 is only seen by quiescence search
+        assert!(info.selective_depth > info.depth);
+    }
+
+    #[test]
+    fn mated_root_has_no_best_move() {
+        let mut engine = engine_at("rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3");
+        let info = engine.search(TimeConstraint::INFINITE, |_| {});
+        assert_eq!(info.best_move, INVALID_MOVE);
+        assert_eq!(info.eval, -Eval::MATE);
+        assert_eq!(info.stop_reason, Some(StopReason::Depth));
+    }
+
+    #[test]
+    fn node_limit_stops_after_an_iteration() {
+        let mut engine = Frozenight::new(1);
+        let mut last = None;
+        let info = engine.search(TimeConstraint::fixed_nodes(10_000), |info| {
+            assert_eq!(info.stop_reason, None);
+            last = Some(info.clone());
+        });
+        let last = last.unwrap();
+        assert_eq!(info.stop_reason, Some(StopReason::Nodes));
+        assert!((10_000..20_100).contains(&info.nodes));
+        assert_eq!(info.best_move, last.best_move);
+        assert_eq!(info.depth, last.depth);
+        if info.nodes < 20_000 {
+            // stopped at the end of an iteration rather than partway through one
+            assert_eq!(info.nodes, last.nodes);
+        }
+    }
+
+    #[test]
+    fn time_limits_end_the_search() {
+        let mut engine = Frozenight::new(1);
+        let movetime = TimeConstraint::movetime(Duration::from_millis(20));
+        let info = engine.search(movetime, |_| {});
+        assert!(info.depth >= 1);
+        assert_eq!(info.stop_reason, Some(StopReason::Time));
+
+        // an overhead larger than the move time still leaves time for an iteration
+        let overhead = TimeConstraint {
+            overhead: Duration::from_millis(50),
+            ..TimeConstraint::movetime(Duration::from_millis(10))
+        };
+        assert!(engine.search(overhead, |_| {}).depth >= 1);
+
+        let clock = TimeConstraint::clock(Duration::from_secs(1), Duration::ZERO);
+        assert!(engine.search(clock, |_| {}).depth >= 1);
+    }
+
+    #[test]
+    fn repetitions_before_the_root() {
+        let start = "7k/8/8/6rq/8/8/8/KN6 w - - 0 1";
+        let cycle = ["b1c3", "h8g8", "c3b1", "g8h8"];
+
+        // Nc3 would repeat a position for the third time
+        let mut engine = Frozenight::new(1);
+        let moves = cycle.iter().chain(&cycle).map(|mv| mv.parse().unwrap());
+        engine.set_position(start.parse().unwrap(), moves);
+        let info = engine.search(TimeConstraint::fixed_depth(1), |_| {});
+        assert_eq!(info.eval, Eval::DRAW);
+        assert_eq!(info.best_move.to_string(), "b1c3");
+
+        // Nc3 would only repeat a position for the second time
+        let mut engine = Frozenight::new(1);
+        let moves = cycle.iter().map(|mv| mv.parse().unwrap());
+        engine.set_position(start.parse().unwrap(), moves);
+        let info = engine.search(TimeConstraint::fixed_depth(1), |_| {});
+        assert!(info.eval < Eval::DRAW);
+    }
+
+    #[test]
+    fn repetitions_need_the_same_side_to_move() {
+        let mut engine = Frozenight::new(1);
+        // the black queen triangulates so the white king reaches a2 with either side to move
+        let start = "1r5k/8/8/7q/8/8/K7/8 w - - 0 1";
+        let moves = "a2a1 h5h6 a1a2 h6g5 a2a1 g5h5 a1a2 h5h6 a2a1 h6g5 a1a2 g5h5 \
+                     a2a1 h5h6 a1a2 h6g5 a2a1 g5h5";
+        engine.set_position(
+            start.parse().unwrap(),
+            moves.split_whitespace().map(|mv| mv.parse().unwrap()),
+        );
+        let info = engine.search(TimeConstraint::fixed_depth(1), |_| {});
+        // Ka2 is forced and the start position occurred twice, but with white to move
+        assert_eq!(info.best_move.to_string(), "a1a2");
+        assert!(info.eval < Eval::DRAW);
+    }
+
+    #[test]
+    fn claimable_draws_are_reported() {
+        let mut engine = Frozenight::new(1);
+        let moves = ["g1f3", "g8f6", "f3g1", "f6g8", "g1f3", "g8f6", "f3g1"];
+        engine.set_position(Board::default(), moves.iter().map(|mv| mv.parse().unwrap()));
+
+        // going back to g8 reaches the start position for the third time
+        let repeat: Move = "f6g8".parse().unwrap();
+        let mut others = vec![];
+        engine.board().generate_moves(|mvs| {
+            others.extend(mvs.into_iter().filter(|&mv| mv != repeat));
+            false
+        });
+
+        engine.set_excluded_moves(others);
+        let info = engine.search(TimeConstraint::fixed_depth(4), |_| {});
+        assert_eq!(info.best_move, repeat);
+        assert!(info.claimable_draw);
+
+        engine.set_excluded_moves(vec![repeat]);
+        let info = engine.search(TimeConstraint::fixed_depth(4), |_| {});
+        assert_ne!(info.best_move, repeat);
+        assert!(!info.claimable_draw);
+    }
+
+    #[test]
+    fn excluded_moves_are_not_played() {
+        let mut engine = engine_at("4k3/8/8/8/q7/8/8/Q3K3 w - - 0 1");
+        let depth_4 = TimeConstraint::fixed_depth(4);
+        assert_eq!(engine.search(depth_4, |_| {}).best_move.to_string(), "a1a4");
+
+        engine.set_excluded_moves(vec!["a1a4".parse().unwrap()]);
+        let info = engine.search(depth_4, |_| {});
+        assert_ne!(info.best_move.to_string(), "a1a4");
+        assert!(engine.board().is_legal(info.best_move));
+
+        // excluding every move excludes none
+        let mut all = vec![];
+        engine.board().generate_moves(|mvs| {
+            all.extend(mvs);
+            false
+        });
+        engine.set_excluded_moves(all);
+        let info = engine.search(depth_4, |_| {});
+        assert!(engine.board().is_legal(info.best_move));
+    }
+
+    #[test]
+    fn smallest_hash_still_searches() {
+        let mut engine = Frozenight::new(1);
+        engine.set_hash(0);
+        let info = engine.search(TimeConstraint::fixed_depth(4), |_| {});
+        assert_eq!(info.depth, 4);
+        assert!(info.hashfull > 0);
+    }
+
+    #[test]
+    fn check_extension_sees_through_a_fork() {
+        let board: Board = "6k1/5ppp/8/4n3/3p4/8/PPP2P1P/3Q2K1 w - - 0 1"
+            .parse()
+            .unwrap();
+        let threads: Vec<_> = (0..2)
+            .map(|_| {
+                let board = board.clone();
+                std::thread::spawn(move || quick_search(&board, 2, 1))
+            })
+            .collect();
+        for thread in threads {
+            let info = thread.join().unwrap();
+            assert_eq!(info.depth, 2);
+            // Qxd4 loses the queen to the Nf3+ fork
+            assert_ne!(info.best_move.to_string(), "d1d4");
+        }
+    }
+
+    #[test]
+    fn incremental_material_survives_promotions() {
+        // debug builds check the incrementally tracked material count after every move
+        let promotions = "8/PPPP1k2/8/8/8/8/1K2pppp/8 w - - 0 1".parse().unwrap();
+        quick_search(&promotions, 6, 1);
+    }
+}
@@ -1,6 +1,7 @@
 use cozy_chess::{Board, Color, File, Move, Piece, Rank, Square};
 
-use crate::Eval;
+use crate::search::params::NNUE_OUTPUT_SCALE;
+use crate::{material_count, Eval};
 
 const NUM_FEATURES: usize = Color::NUM * Piece::NUM * Square::NUM;
 const L1_SIZE: usize = 384;
@@ -42,16 +43,17 @@ impl NnueAccumulator {
         NnueAccumulator {
             white,
             black,
-            material: board.pieces(Piece::Pawn).len() as usize
-                + 3 * board.pieces(Piece::Bishop).len() as usize
-                + 3 * board.pieces(Piece::Knight).len() as usize
-                + 5 * board.pieces(Piece::Rook).len() as usize
-                + 8 * board.pieces(Piece::Queen).len() as usize,
+            material: material_count(board),
         }
     }
 
+    /// The [`material_count`] of the position, kept up to date incrementally.
+    pub fn material(&self) -> usize {
+        self.material
+    }
+
     pub fn calculate(&self, stm: Color) -> Eval {
-        let bucket = (self.material * BUCKETS / 76).min(BUCKETS - 1);
+        let bucket = bucket(self.material);
         let mut output = NETWORK.hidden_layer_bias[bucket] * 127;
         let (first, second) = match stm {
             Color::White => (&self.white, &self.black),
@@ -64,7 +66,7 @@ impl NnueAccumulator {
             output += activate(second[i]) * NETWORK.hidden_layer[bucket][i + first.len()] as i32;
         }
 
-        Eval::new((output / 127 / 8) as i16)
+        scale_output(output, NNUE_OUTPUT_SCALE.get() as i32)
     }
 
     pub fn play_move(&self, board: &Board, mv: Move) -> Self {
@@ -196,12 +198,40 @@ impl NnueAccumulator {
     }
 }
 
+/// The network's evaluation of `board` for the side to move, without any search.
+pub fn static_eval_board(board: &Board) -> Eval {
+    NnueAccumulator::new(board).calculate(board.side_to_move())
+}
+
+/// Converts the raw output of the network to an eval. Small scales can push the result outside
+/// the range of inconclusive evals, or even of `i16`, so it is clamped before the conversion.
+fn scale_output(output: i32, scale: i32) -> Eval {
+    let limit = Eval::MAX_INCONCLUSIVE.raw() as i32;
+    Eval::new((output / 127 / scale).clamp(-limit, limit) as i16)
+}
+
+/// Squared clipped ReLU, the hidden layer activation nets are trained with by default.
+#[cfg(not(feature = "crelu"))]
 fn activate(v: i16) -> i32 {
     let v = v as i32;
     let v = v.clamp(0, 127);
     v * v
 }
 
+/// Clipped ReLU, scaled up to the same range as the squared activation so that the output layer
+/// is computed the same way for both.
+#[cfg(feature = "crelu")]
+fn activate(v: i16) -> i32 {
+    let v = v as i32;
+    v.clamp(0, 127) * 127
+}
+
+/// The output bucket for a position with the given material. Promotions can raise the material
+/// above the 76 of the starting position, so it is capped to keep the index in range.
+fn bucket(material: usize) -> usize {
+    (material.min(76) * BUCKETS / 76).min(BUCKETS - 1)
+}
+
 fn vadd<const N: usize>(a: &mut [i16; N], b: &[i16; N]) {
     a.iter_mut().zip(b.iter()).for_each(|(a, &b)| *a += b);
 }
@@ -213,3 +243,46 @@ fn vsub<const N: usize>(a: &mut [i16; N], b: &[i16; N]) {
 fn feature(color: Color, piece: Piece, sq: Square) -> usize {
     sq as usize + Square::NUM * (piece as usize + Piece::NUM * color as usize)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_scale_is_monotonic() {
+        let outputs = [
+            -1 << 30,
+            -5_000_000,
+            -40_000,
+            -127,
+            0,
+            127,
+            40_000,
+            5_000_000,
+            1 << 30,
+        ];
+        for scale in 1..=64 {
+            let evals: Vec<_> = outputs.iter().map(|&o| scale_output(o, scale)).collect();
+            assert!(evals.windows(2).all(|w| w[0] <= w[1]));
+            assert_eq!(scale_output(0, scale), Eval::DRAW);
+
+            // a larger scale never makes an eval more extreme or changes its sign
+            for &output in &outputs {
+                let eval = scale_output(output, scale).raw();
+                let larger = scale_output(output, scale + 1).raw();
+                assert!(larger.abs() <= eval.abs());
+                assert!(larger.signum() * eval.signum() >= 0);
+            }
+        }
+        assert_eq!(scale_output(1 << 30, 1), Eval::MAX_INCONCLUSIVE);
+        assert_eq!(scale_output(-1 << 30, 1), -Eval::MAX_INCONCLUSIVE);
+    }
+
+    #[test]
+    fn static_eval_is_symmetric() {
+        let white: Board = "4k3/pp3ppp/8/8/3N4/8/PP3PPP/4K3 w - - 0 1".parse().unwrap();
+        let black: Board = "4k3/pp3ppp/8/3n4/8/8/PP3PPP/4K3 b - - 0 1".parse().unwrap();
+        assert_eq!(static_eval_board(&white), static_eval_board(&black));
+        assert!(static_eval_board(&white) > Eval::DRAW);
+    }
+}
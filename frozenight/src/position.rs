@@ -1,7 +1,8 @@
 use std::cell::Cell;
 
-use cozy_chess::{Board, Move};
+use cozy_chess::{Board, Move, Piece};
 
+use crate::material_count;
 use crate::nnue::NnueAccumulator;
 use crate::Eval;
 use crate::tt::TranspositionTable;
@@ -10,6 +11,8 @@ use crate::tt::TranspositionTable;
 pub struct Position {
     pub board: Board,
     pub ply: u16,
+    /// The number of checking moves played since the root.
+    pub checks: u16,
     nnue: NnueAccumulator,
     eval: Cell<Option<Eval>>,
 }
@@ -20,17 +23,36 @@ impl Position {
             nnue: NnueAccumulator::new(&board),
             board,
             ply: 0,
+            checks: 0,
             eval: Cell::default(),
         }
     }
 
+    /// Plays `mv`, updating the accumulator incrementally. Castling is encoded as the king moving
+    /// onto its own rook.
     pub fn play_move(&self, mv: Move, tt: &TranspositionTable) -> Position {
         let mut board = self.board.clone();
         board.play_unchecked(mv);
         tt.prefetch(&board);
+        let nnue = self.nnue.play_move(&self.board, mv);
+        debug_assert_eq!(
+            nnue.material(),
+            material_count(&board),
+            "incremental material diverged after {} from {}",
+            mv,
+            self.board
+        );
+        debug_assert!(
+            self.board.color_on(mv.to) != Some(self.board.side_to_move())
+                || nnue == NnueAccumulator::new(&board),
+            "incremental accumulator diverged after castling {} from {}",
+            mv,
+            self.board
+        );
         Position {
+            checks: self.checks + !board.checkers().is_empty() as u16,
             board,
-            nnue: self.nnue.play_move(&self.board, mv),
+            nnue,
             ply: self.ply + 1,
             eval: Cell::default(),
         }
@@ -43,6 +65,7 @@ impl Position {
                 board,
                 nnue: self.nnue,
                 ply: self.ply + 1,
+                checks: self.checks,
                 eval: Cell::default(),
             }
         })
@@ -52,7 +75,10 @@ impl Position {
         match self.eval.get() {
             Some(v) => v,
             None => {
+                #[cfg(not(feature = "no-nnue"))]
                 let v = self.nnue.calculate(self.board.side_to_move());
+                #[cfg(feature = "no-nnue")]
+                let v = crate::classical_eval(&self.board);
                 self.eval.set(Some(v));
                 v
             }
@@ -60,6 +86,85 @@ impl Position {
     }
 
     pub fn is_capture(&self, mv: Move) -> bool {
+        // a pawn moving diagonally onto an empty square captures en passant
         self.board.colors(!self.board.side_to_move()).has(mv.to)
+            || self.board.pieces(Piece::Pawn).has(mv.from) && mv.from.file() != mv.to.file()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cozy_chess::{Board, File, Piece, Rank, Square};
+
+    use super::*;
+
+    /// Every Chess960 start with the back ranks cleared except for the kings and rooks, so most
+    /// positions can castle both ways.
+    fn castling_positions() -> impl Iterator<Item = Board> {
+        (0..960).flat_map(|id| {
+            let start = Board::chess960_startpos(id);
+            let mut back_rank = String::new();
+            let mut castling = String::new();
+            let mut empty = 0;
+            for file in File::ALL {
+                let c = match start.piece_on(Square::new(file, Rank::First)) {
+                    Some(Piece::King) => 'k',
+                    Some(Piece::Rook) => 'r',
+                    _ => {
+                        empty += 1;
+                        continue;
+                    }
+                };
+                if empty > 0 {
+                    back_rank += &empty.to_string();
+                    empty = 0;
+                }
+                back_rank.push(c);
+                if c == 'r' {
+                    castling.push((b'a' + file as u8) as char);
+                }
+            }
+            if empty > 0 {
+                back_rank += &empty.to_string();
+            }
+
+            ["w", "b"].map(|stm| {
+                let fen = format!(
+                    "{}/pppppppp/8/8/8/8/PPPPPPPP/{} {} {}{} - 0 1",
+                    back_rank,
+                    back_rank.to_uppercase(),
+                    stm,
+                    castling.to_uppercase(),
+                    castling,
+                );
+                Board::from_fen(&fen, true).unwrap()
+            })
+        })
+    }
+
+    #[test]
+    fn castling_updates_the_accumulator() {
+        let mut castles = 0;
+        for board in castling_positions() {
+            board.generate_moves(|mvs| {
+                for mv in mvs {
+                    if board.color_on(mv.to) != Some(board.side_to_move()) {
+                        continue;
+                    }
+                    castles += 1;
+                    let mut after = board.clone();
+                    after.play(mv);
+                    assert_eq!(
+                        NnueAccumulator::new(&board).play_move(&board, mv),
+                        NnueAccumulator::new(&after),
+                        "castling {} from {}",
+                        mv,
+                        board
+                    );
+                }
+                false
+            });
+        }
+        assert!(castles > 960 * 2);
     }
 }
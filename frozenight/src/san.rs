@@ -0,0 +1,103 @@
+use cozy_chess::{Board, GameStatus, Move, Piece};
+
+/// Formats `mv` in standard algebraic notation, as used in PGN. `mv` must be legal in `board`.
+pub fn move_to_san(board: &Board, mv: Move) -> String {
+    let piece = board.piece_on(mv.from).unwrap();
+    let from = mv.from.to_string();
+    let mut san = String::new();
+
+    if piece == Piece::King && board.color_on(mv.to) == Some(board.side_to_move()) {
+        // castling is encoded as the king capturing its own rook
+        san += match mv.to.file() > mv.from.file() {
+            true => "O-O",
+            false => "O-O-O",
+        };
+    } else {
+        let capture = board.color_on(mv.to).is_some()
+            || piece == Piece::Pawn && mv.from.file() != mv.to.file();
+
+        if piece == Piece::Pawn {
+            if capture {
+                san += &from[..1];
+            }
+        } else {
+            san.push(piece_char(piece));
+
+            let mut others = vec![];
+            board.generate_moves(|mvs| {
+                if mvs.piece == piece && mvs.from != mv.from && mvs.to.has(mv.to) {
+                    others.push(mvs.from);
+                }
+                false
+            });
+            if !others.is_empty() {
+                let same_file = others.iter().any(|sq| sq.file() == mv.from.file());
+                let same_rank = others.iter().any(|sq| sq.rank() == mv.from.rank());
+                match () {
+                    _ if !same_file => san += &from[..1],
+                    _ if !same_rank => san += &from[1..],
+                    _ => san += &from,
+                }
+            }
+        }
+
+        if capture {
+            san.push('x');
+        }
+        san += &mv.to.to_string();
+        if let Some(promotion) = mv.promotion {
+            san.push('=');
+            san.push(piece_char(promotion));
+        }
+    }
+
+    let mut after = board.clone();
+    after.play_unchecked(mv);
+    if after.status() == GameStatus::Won {
+        san.push('#');
+    } else if !after.checkers().is_empty() {
+        san.push('+');
+    }
+    san
+}
+
+fn piece_char(piece: Piece) -> char {
+    match piece {
+        Piece::Pawn => 'P',
+        Piece::Knight => 'N',
+        Piece::Bishop => 'B',
+        Piece::Rook => 'R',
+        Piece::Queen => 'Q',
+        Piece::King => 'K',
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn san(fen: &str, mv: &str) -> String {
+        let board: Board = fen.parse().unwrap();
+        move_to_san(&board, mv.parse().unwrap())
+    }
+
+    #[test]
+    fn disambiguation() {
+        // knights on different files
+        assert_eq!(san("4k3/8/8/8/8/5N2/8/1N2K3 w - - 0 1", "b1d2"), "Nbd2");
+        // knights on the same file
+        assert_eq!(san("4k3/8/8/8/2N5/8/2N5/4K3 w - - 0 1", "c2e3"), "N2e3");
+        // a pinned knight doesn't need to be told apart
+        assert_eq!(san("4k3/8/8/8/8/5N2/8/rN2K3 w - - 0 1", "f3d2"), "Nd2");
+    }
+
+    #[test]
+    fn special_moves() {
+        assert_eq!(san("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "e5d6"), "exd6");
+        assert_eq!(san("4k3/8/8/3p4/4P3/8/8/4K3 w - - 0 1", "e4e5"), "e5");
+        assert_eq!(san("4k3/8/8/8/8/8/8/R3K2R w KQ - 0 1", "e1h1"), "O-O");
+        assert_eq!(san("4k3/8/8/8/8/8/8/R3K2R w KQ - 0 1", "e1a1"), "O-O-O");
+        assert_eq!(san("4k3/8/8/8/8/8/8/R3K3 w - - 0 1", "a1a8"), "Ra8+");
+        assert_eq!(san("k7/4P3/1K6/8/8/8/8/8 w - - 0 1", "e7e8q"), "e8=Q#");
+    }
+}
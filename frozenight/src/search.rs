@@ -1,31 +1,74 @@
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
 use cozy_chess::{Board, Move, Square};
 
 use crate::position::Position;
-use crate::tt::{NodeKind, TableEntry};
-use crate::{Eval, Frozenight, SharedState, Statistics};
+use crate::tt::{NodeKind, TableEntry, TranspositionTable};
+use crate::{game_phase, root_exclusions, Eval, Frozenight, SharedState, Statistics, StopReason};
 
-use self::ordering::{OrderingState, BREAK, CONTINUE};
+pub use self::oracle::is_dead_draw;
+use self::ordering::{MoveScore, OrderingState, BREAK, CONTINUE};
 pub use self::params::all_parameters;
+#[cfg(feature = "tweakable")]
+pub use self::params::dump_spsa_config;
+use self::params::{contempt, ASP_GROWTH, ASP_WINDOW, HISTORY_MERGE, MAX_CHECK_EXTENSIONS};
 use self::window::Window;
 
+/// Writes a line to stderr if `position` is the root of a search started by
+/// [`trace_root`](crate::trace_root). Without the `trace` feature, this compiles to nothing.
+#[cfg(feature = "trace")]
+macro_rules! trace {
+    ($position:expr, $($arg:tt)*) => {
+        if $position.ply == 0 && $crate::search::TRACE.with(|t| t.get()) {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+#[cfg(not(feature = "trace"))]
+macro_rules! trace {
+    ($($arg:tt)*) => {};
+}
+
 mod null;
 mod oracle;
 mod ordering;
-mod params;
+pub(crate) mod params;
 mod pv;
 mod qsearch;
 mod see;
 mod window;
 
+#[cfg(feature = "trace")]
+thread_local! {
+    /// Whether searches on this thread trace their root node.
+    pub(crate) static TRACE: std::cell::Cell<bool> = std::cell::Cell::new(false);
+}
+
+/// Placeholder reported as the best move when the root position has no legal moves.
 pub const INVALID_MOVE: Move = Move {
     from: Square::A1,
     to: Square::A1,
     promotion: None,
 };
 
+/// The deepest iteration a search runs. Searches that should go on until they are stopped wait
+/// for the stop once they get here, rather than iterating deeper than depths can represent.
+pub(crate) const MAX_DEPTH: i16 = 250;
+
+/// History shared between search threads. When enabled, each thread blends its own history with
+/// this at the start of every iteration, so threads learn from each other's cutoffs.
+#[derive(Default)]
+pub(crate) struct SharedHistory(Mutex<OrderingState>);
+
+impl SharedHistory {
+    pub fn clear(&self) {
+        self.0.lock().unwrap().clear();
+    }
+}
+
 pub(crate) struct PrivateState {
     history: OrderingState,
 }
@@ -38,19 +81,42 @@ impl Default for PrivateState {
     }
 }
 
+impl PrivateState {
+    /// Forgets everything learned from previous searches, reusing the existing tables.
+    pub fn clear(&mut self) {
+        self.history.clear();
+    }
+}
+
 pub(crate) struct Searcher<'a> {
     pub root: &'a Board,
     pub stats: &'a Statistics,
     pub shared: &'a SharedState,
     pub node_limit: u64,
     pub abort: &'a AtomicBool,
+    /// Why the search was cut short, once it has been.
+    pub stop_reason: Option<StopReason>,
+    /// The score of each root move from the most recent pass over them, in search order.
+    pub root_scores: Vec<(Move, Eval)>,
+    /// Root moves that must not be searched.
+    pub excluded: &'a [Move],
     state: &'a mut PrivateState,
+    /// The root moves in order of their scores in the most recently completed iteration, best
+    /// first. Moves with equal scores keep the order they were searched in.
+    root_order: Vec<Move>,
+    /// Set while verifying a null move cutoff, during which null move pruning is disabled.
+    verifying_null_move: bool,
+    /// Whether other threads search with the same shared state, so that there is history to
+    /// merge with theirs.
+    share_history: bool,
     valid: bool,
     allow_abort: bool,
     deadline: Option<Instant>,
+    start: Instant,
     next_deadline_check: u64,
     rep_list: Vec<u64>,
     rep_table: [u8; 1024],
+    root_index: usize,
 }
 
 impl Frozenight {
@@ -66,25 +132,36 @@ impl Frozenight {
         for &b in &self.prehistory {
             rep_table[b as usize % 1024] += 1;
         }
+        // an `MtFrozenight` holds a reference to the shared state besides its threads
+        let share_history = Arc::strong_count(&self.shared_state) > 2;
         let shared = self.shared_state.read().unwrap();
+        let start = Instant::now();
         f(Searcher {
             root: &self.board,
             shared: &shared,
             abort,
+            stop_reason: None,
+            root_scores: vec![],
+            excluded: root_exclusions(&self.board, &self.excluded),
             state: &mut self.state,
+            root_order: vec![],
+            verifying_null_move: false,
+            share_history,
             stats: &self.stats,
             rep_table,
             node_limit,
             deadline,
+            start,
             next_deadline_check: match deadline {
                 Some(deadline) => deadline
-                    .checked_duration_since(Instant::now())
-                    .map_or(0, estimate_nodes_to_deadline),
+                    .checked_duration_since(start)
+                    .map_or(0, |d| estimate_nodes_to_deadline(d, 0, Duration::ZERO)),
                 None => u64::MAX,
             },
             valid: true,
             allow_abort: false,
             rep_list: self.prehistory.clone(),
+            root_index: self.prehistory.len() - 1,
         })
     }
 }
@@ -105,20 +182,50 @@ impl<'a> Searcher<'a> {
             panic!("root position (FEN: {}) has no moves", self.root);
         }
 
-        let window = match () {
+        if HISTORY_MERGE.get() != 0 && self.share_history && depth > 1 {
+            let mut shared = self.shared.history.0.lock().unwrap();
+            shared.blend(&self.state.history);
+            self.state.history.blend(&shared);
+        }
+
+        let mut delta = ASP_WINDOW.get() as i32;
+        let mut window = match () {
             _ if depth < 3 => Window::default(),
             _ if around.is_conclusive() => Window::default(),
-            _ => Window::new(around - 500, around + 500),
+            _ => Window::new(
+                aspiration_bound(around, -delta),
+                aspiration_bound(around, delta),
+            ),
         };
 
         let position = &Position::from_root(self.root.clone());
 
-        let (eval, mv) = self.pv_search(position, window, depth)?;
+        loop {
+            let (eval, mv) = self.pv_search(position, window, depth)?;
 
-        if window.fail_low(eval) || window.fail_high(eval) {
-            self.pv_search(position, Window::default(), depth)
-        } else {
-            Some((eval, mv))
+            if !window.fail_low(eval) && !window.fail_high(eval) {
+                let mut scores = self.root_scores.clone();
+                scores.sort_by_key(|&(_, v)| std::cmp::Reverse(v));
+                self.root_order = scores.into_iter().map(|(mv, _)| mv).collect();
+                return Some((eval, mv));
+            }
+
+            // widen the side of the window that failed until it is unbounded
+            delta = widen_aspiration(delta, ASP_GROWTH.get() as i32);
+            if window.fail_low(eval) {
+                self.stats
+                    .aspiration_fail_lows
+                    .fetch_add(1, Ordering::Relaxed);
+                window = Window::new(aspiration_bound(around, -delta), window.ub());
+            } else {
+                self.stats
+                    .aspiration_fail_highs
+                    .fetch_add(1, Ordering::Relaxed);
+                window = Window::new(window.lb(), aspiration_bound(around, delta));
+            }
+            self.stats
+                .aspiration_researches
+                .fetch_add(1, Ordering::Relaxed);
         }
     }
 
@@ -130,34 +237,34 @@ impl<'a> Searcher<'a> {
         f: impl FnOnce(&mut Self) -> Option<Eval>,
     ) -> Option<Eval> {
         match position.board.status() {
-            cozy_chess::GameStatus::Drawn => return Some(Eval::DRAW),
+            cozy_chess::GameStatus::Drawn => return Some(self.draw_score(&position.board)),
             cozy_chess::GameStatus::Won => return Some(-Eval::MATE.add_time(position.ply)),
             cozy_chess::GameStatus::Ongoing => {}
         }
 
         if self.allow_abort && self.abort.load(Ordering::Relaxed) {
+            self.stop_reason = Some(StopReason::Abort);
             return None;
         }
 
         let result = if depth <= 0 {
-            self.stats
-                .selective_depth
-                .fetch_max(position.ply as i16, Ordering::Relaxed);
             self.qsearch(position, window)
         } else {
             let nodes = self.stats.nodes.fetch_add(1, Ordering::Relaxed);
             if self.allow_abort {
                 if nodes >= self.node_limit {
+                    self.stop_reason = Some(StopReason::Nodes);
                     return None;
                 }
                 if let Some(deadline) = self.deadline {
                     if nodes > self.next_deadline_check {
                         let now = Instant::now();
                         if now >= deadline {
+                            self.stop_reason = Some(StopReason::Time);
                             return None;
                         }
-                        self.next_deadline_check =
-                            nodes + estimate_nodes_to_deadline(deadline - now);
+                        self.next_deadline_check = nodes
+                            + estimate_nodes_to_deadline(deadline - now, nodes, now - self.start);
                     }
                 }
             }
@@ -173,33 +280,55 @@ impl<'a> Searcher<'a> {
         Some(result)
     }
 
+    /// Searches the moves of `position`, using `f` to score each one after it is played. `prune`
+    /// is asked first and can give a move a score without playing it, which saves updating the
+    /// network for moves that won't be searched.
     fn search_moves(
         &mut self,
         position: &Position,
         hashmove: Option<Move>,
         mut window: Window,
         depth: i16,
-        mut f: impl FnMut(&mut Searcher, usize, Move, &Position, Window) -> Option<Eval>,
+        mut prune: impl FnMut(&Searcher, usize, Move, Window) -> Option<Eval>,
+        mut f: impl FnMut(&mut Searcher, usize, Move, MoveScore, &Position, Window) -> Option<Eval>,
     ) -> Option<(Eval, Move)> {
         let mut best_move = INVALID_MOVE;
         let mut best_score = -Eval::MATE;
         let mut raised_alpha = false;
         let mut i = 0;
 
-        self.visit_moves(position, hashmove, |this, mv| {
-            let new_pos = position.play_move(mv, &this.shared.tt);
+        if position.ply == 0 {
+            self.root_scores.clear();
+        }
+
+        self.visit_moves(position, hashmove, |this, mv, score| {
+            if position.ply == 0 && this.excluded.contains(&mv) {
+                return Some(CONTINUE);
+            }
+
             i += 1;
             let i = i - 1;
 
-            let v;
-            if let Some(eval) = oracle::oracle(&new_pos.board) {
-                v = eval;
-            } else if this.is_repetition(&new_pos.board) {
-                v = Eval::DRAW;
-            } else {
-                this.push_repetition(&new_pos.board);
-                v = f(this, i, mv, &new_pos, window)?;
-                this.pop_repetition();
+            let v = match prune(this, i, mv, window) {
+                Some(bound) => bound,
+                None => {
+                    let new_pos = position.play_move(mv, &this.shared.tt);
+                    if let Some(eval) = oracle::oracle(&new_pos.board) {
+                        eval
+                    } else if this.is_repetition(&new_pos.board) {
+                        this.draw_score(&position.board)
+                    } else {
+                        this.push_repetition(&new_pos.board);
+                        let v = f(this, i, mv, score, &new_pos, window)?;
+                        this.pop_repetition();
+                        v
+                    }
+                }
+            };
+            trace!(position, "    {} scored {}", mv, v);
+
+            if position.ply == 0 {
+                this.root_scores.push((mv, v));
             }
 
             if v > best_score {
@@ -262,6 +391,16 @@ impl<'a> Searcher<'a> {
         self.state.history.caused_cutoff(position, mv, depth);
     }
 
+    /// The score of a draw for the side to move in `board`. Contempt makes a draw worse for the
+    /// side to move at the root, and so better for its opponent.
+    fn draw_score(&self, board: &Board) -> Eval {
+        let contempt = contempt(game_phase(board));
+        match board.side_to_move() == self.root.side_to_move() {
+            true => Eval::new(-contempt),
+            false => Eval::new(contempt),
+        }
+    }
+
     fn push_repetition(&mut self, board: &Board) {
         self.rep_table[board.hash() as usize % 1024] += 1;
         self.rep_list.push(board.hash());
@@ -272,34 +411,210 @@ impl<'a> Searcher<'a> {
         self.rep_table[hash as usize % 1024] -= 1;
     }
 
+    /// Marks a null move in `rep_list`. This keeps the list aligned with the side to move without
+    /// adding the position after the null move, which isn't a real position of the game.
+    fn push_null_move(&mut self) {
+        self.rep_list.push(NULL_MOVE_MARKER);
+    }
+
+    fn pop_null_move(&mut self) {
+        let marker = self.rep_list.pop();
+        debug_assert_eq!(marker, Some(NULL_MOVE_MARKER));
+    }
+
+    /// Whether `board` repeats a position since the last irreversible move. Positions in the
+    /// search tree, including the root, count as a draw on their first repetition, while
+    /// positions from before the root are only in `rep_list` once they occurred twice.
+    ///
+    /// A null move is a barrier: positions before it can't be repeated by positions after it.
     fn is_repetition(&self, board: &Board) -> bool {
         if self.rep_table[board.hash() as usize % 1024] == 0 {
             return false;
         }
 
-        self.rep_list
+        let window = board.halfmove_clock() as usize;
+        let (prehistory, tree) = self.rep_list.split_at(self.root_index);
+        let null_move = tree.iter().rposition(|&b| b == NULL_MOVE_MARKER);
+        let tree = match null_move {
+            Some(i) => &tree[i + 1..],
+            None => tree,
+        };
+
+        // the side to move alternates along the search path, so only every other position can
+        // be the same as this one
+        let in_tree = tree
             .iter()
             .rev()
-            .take(board.halfmove_clock() as usize)
+            .take(window)
             .skip(1)
-            .any(|&b| b == board.hash())
+            .step_by(2)
+            .any(|&b| b == board.hash());
+
+        // positions from before the root are not stored in game order, so check all of them
+        in_tree || null_move.is_none() && window > tree.len() && prehistory.contains(&board.hash())
     }
 
-    pub fn extract_pv(&mut self, depth: i16) -> Vec<Move> {
+    /// Whether playing `mv` from the root lets the side to move claim a draw, either because it
+    /// repeats a position for the third time or because it completes fifty moves without a
+    /// capture or pawn move.
+    pub fn claimable_draw(&self, mv: Move) -> bool {
         let mut board = self.root.clone();
-        let mut pv = Vec::with_capacity(16);
-        while let Some(mv) = self.shared.tt.get_move(&board) {
-            pv.push(mv);
-            board.play_unchecked(mv);
-            if pv.len() > depth as usize {
-                break;
-            }
+        board.play_unchecked(mv);
+        let prehistory = &self.rep_list[..self.root_index];
+        board.halfmove_clock() >= 100 || prehistory.contains(&board.hash())
+    }
+
+    pub fn extract_pv(&mut self, depth: i16) -> Vec<Move> {
+        extract_line(&self.shared.tt, self.root.clone(), depth as usize + 1)
+    }
+}
+
+/// Stands in for the position after a null move in the repetition list. A real position hashing
+/// to this is astronomically unlikely, and would at worst hide a repetition.
+const NULL_MOVE_MARKER: u64 = 0;
+
+/// Follows the moves stored in the transposition table from `board`, up to `max_len` of them.
+pub(crate) fn extract_line(tt: &TranspositionTable, mut board: Board, max_len: usize) -> Vec<Move> {
+    let mut line = Vec::with_capacity(16);
+    while let Some(mv) = tt.get_move(&board) {
+        line.push(mv);
+        board.play_unchecked(mv);
+        if line.len() >= max_len {
+            break;
         }
-        pv
+    }
+    line
+}
+
+/// Extends a move that gives check by a ply, but only for the first `MAX_CHECK_EXTENSIONS` checks
+/// along the line, so that long checking sequences can't blow up the search.
+fn check_extension(position: &Position) -> i16 {
+    match () {
+        _ if position.checks > MAX_CHECK_EXTENSIONS.get() as u16 => 0,
+        _ if !position.board.checkers().is_empty() => 1,
+        _ => 0,
+    }
+}
+
+/// Offsets `around` by `delta`, or gives an unbounded window edge once that is no longer an
+/// inconclusive eval.
+fn aspiration_bound(around: Eval, delta: i32) -> Eval {
+    let bound = around.raw() as i32 + delta;
+    match () {
+        _ if bound >= Eval::MAX_INCONCLUSIVE.raw() as i32 => Eval::MATE,
+        _ if bound <= -Eval::MAX_INCONCLUSIVE.raw() as i32 => -Eval::MATE,
+        _ => Eval::new(bound as i16),
     }
 }
 
-fn estimate_nodes_to_deadline(d: Duration) -> u64 {
-    // assume we get at least 1 mnps (very conservative)
-    1000 * d.as_millis().min(1) as u64
+/// Grows an aspiration `delta` by `growth` sixteenths. It always grows by at least one, since
+/// rounding would otherwise leave small deltas unchanged and the root would re-search forever.
+fn widen_aspiration(delta: i32, growth: i32) -> i32 {
+    (delta * growth / 16).max(delta + 1)
+}
+
+/// Estimates how many nodes can be searched before the clock should be checked again, given the
+/// time `remaining` until the deadline and the `nodes` searched in the `elapsed` time so far.
+///
+/// This aims to check again about halfway to the deadline, so checks become more frequent as the
+/// deadline approaches without costing much time when it is far away.
+fn estimate_nodes_to_deadline(remaining: Duration, nodes: u64, elapsed: Duration) -> u64 {
+    let nps = match elapsed.as_micros() {
+        // too little has been searched to measure; assume 1 mnps (conservative)
+        0..=999 => 1_000_000,
+        micros => nodes as u128 * 1_000_000 / micros,
+    };
+    (remaining.as_micros() * nps / 2_000_000).max(1) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The number of times a window around `around` is widened on the side of `sign` before that
+    /// side becomes unbounded.
+    fn widenings_to_unbounded(around: Eval, delta: i32, growth: i32, sign: i32) -> usize {
+        let mut delta = delta;
+        let mut widenings = 0;
+        while !aspiration_bound(around, sign * delta).is_conclusive() {
+            delta = widen_aspiration(delta, growth);
+            widenings += 1;
+        }
+        widenings
+    }
+
+    #[test]
+    fn check_extensions_are_limited() {
+        let tt = TranspositionTable::new(1);
+        let root = Position::from_root("4k3/8/8/8/8/8/8/3QK3 w - - 0 1".parse().unwrap());
+        let mut check = root.play_move("d1a4".parse().unwrap(), &tt);
+        assert_eq!(check.checks, 1);
+        assert_eq!(check_extension(&root), 0);
+
+        // every check along the line counts towards the limit, so at most that many are extended
+        let extended = (1..100)
+            .filter(|&n| {
+                check.checks = n;
+                check_extension(&check) > 0
+            })
+            .count();
+        assert_eq!(extended, MAX_CHECK_EXTENSIONS.get() as usize);
+    }
+
+    #[test]
+    fn perpetual_check_search_stays_bounded() {
+        let mut engine = Frozenight::new(1);
+        let fen = "k7/8/1q6/8/8/6Q1/8/7K w - - 0 1";
+        engine.set_position(fen.parse().unwrap(), std::iter::empty());
+        let time = crate::TimeConstraint {
+            depth: 6,
+            nodes: 1_000_000,
+            ..crate::TimeConstraint::INFINITE
+        };
+        let info = engine.search(time, |_| {});
+        assert_eq!(info.stop_reason, Some(StopReason::Depth));
+    }
+
+    #[test]
+    fn iterations_stop_at_max_depth() {
+        // every move leaves a dead draw, so iterations are cheap however deep they go
+        let mut engine = Frozenight::new(1);
+        let fen = "8/8/8/4k3/8/8/8/N6K w - - 0 1";
+        engine.set_position(fen.parse().unwrap(), std::iter::empty());
+        let info = engine.search(crate::TimeConstraint::INFINITE, |_| {});
+        assert_eq!(info.stop_reason, Some(StopReason::Depth));
+        assert_eq!(info.depth, MAX_DEPTH);
+    }
+
+    #[test]
+    fn deadline_checks_scale_with_remaining_time_and_speed() {
+        let estimate = estimate_nodes_to_deadline;
+        let second = Duration::from_secs(1);
+        // too little has been searched to measure the speed, so 1 mnps is assumed
+        assert_eq!(estimate(second, 0, Duration::ZERO), 500_000);
+        // 2 mnps measured, checking again halfway to the deadline
+        assert_eq!(estimate(second, 2_000_000, second), 1_000_000);
+        assert_eq!(estimate(second / 10, 2_000_000, second), 100_000);
+        // the clock is always checked again eventually
+        assert_eq!(estimate(Duration::ZERO, 2_000_000, second), 1);
+    }
+
+    #[test]
+    fn aspiration_window_becomes_unbounded() {
+        let limit = Eval::MAX_INCONCLUSIVE.raw();
+        for around in [-limit, -1000, 0, 1000, limit] {
+            for sign in [-1, 1] {
+                // the slowest growth the tweakable ranges allow
+                assert!(widenings_to_unbounded(Eval::new(around), 1, 17, sign) < 200);
+                // the defaults
+                let default = widenings_to_unbounded(
+                    Eval::new(around),
+                    ASP_WINDOW.get() as i32,
+                    ASP_GROWTH.get() as i32,
+                    sign,
+                );
+                assert!(default < 10);
+            }
+        }
+    }
 }
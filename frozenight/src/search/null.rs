@@ -2,11 +2,13 @@ use crate::position::Position;
 use crate::tt::NodeKind;
 use crate::Eval;
 
+use super::ordering::MoveScore;
 use super::params::*;
+use super::qsearch::gives_check;
 use super::window::Window;
-use super::Searcher;
+use super::{check_extension, Searcher};
 
-use cozy_chess::Piece;
+use cozy_chess::{Board, Piece};
 
 impl Searcher<'_> {
     pub fn visit_null(&mut self, position: &Position, window: Window, depth: i16) -> Option<Eval> {
@@ -15,6 +17,22 @@ impl Searcher<'_> {
         })
     }
 
+    /// Searches the position after a move with the null `window` of its parent, reduced by
+    /// `reduction` plies. A reduced search that beats the lower bound can't be trusted, so it is
+    /// verified at full depth. Both the PV and null window searches go through this so their
+    /// re-search conditions can't drift apart.
+    pub fn reduced_search(
+        &mut self,
+        new_pos: &Position,
+        window: Window,
+        depth: i16,
+        reduction: i16,
+    ) -> Option<Eval> {
+        research_reduced(window, depth, reduction, |depth| {
+            Some(-self.visit_null(new_pos, -window, depth)?)
+        })
+    }
+
     fn null_search(&mut self, position: &Position, window: Window, depth: i16) -> Option<Eval> {
         let entry = self.shared.tt.get(position);
         if let Some(entry) = entry {
@@ -43,8 +61,11 @@ impl Searcher<'_> {
         // reverse futility pruning... but with qsearch
         if depth <= RFP_MAX_DEPTH.get() {
             let rfp_window = Window::null(window.lb() + rfp_margin(depth));
+            // a mate score in the TT is a search result rather than an estimate of the position,
+            // so it can't stand in for the static eval here
             let eval = entry
                 .map(|e| e.eval)
+                .filter(|eval| !eval.is_conclusive())
                 .unwrap_or_else(|| self.qsearch(position, rfp_window));
             if rfp_window.fail_high(eval) {
                 return Some(eval);
@@ -52,12 +73,9 @@ impl Searcher<'_> {
         }
 
         // null move pruning
-        let our_sliders = (position.board.pieces(Piece::Rook)
-            | position.board.pieces(Piece::Bishop)
-            | position.board.pieces(Piece::Queen))
-            & position.board.colors(position.board.side_to_move());
         let do_nmp = depth >= NMP_MIN_DEPTH.get()
-            && !our_sliders.is_empty()
+            && !self.verifying_null_move
+            && null_move_allowed(&position.board)
             && window.fail_high(position.static_eval());
         if do_nmp {
             if let Some(nm) = position.null_move(&self.shared.tt) {
@@ -65,9 +83,24 @@ impl Searcher<'_> {
                     depth,
                     position.static_eval().raw() as i32 - window.ub().raw() as i32,
                 );
-                let v = -self.visit_null(&nm, -window, depth - reduction - 1)?;
+                self.push_null_move();
+                let v = self.visit_null(&nm, -window, depth - reduction - 1);
+                // pop before an aborted search returns, so the null move stack stays balanced
+                self.pop_null_move();
+                let v = -v?;
                 if window.fail_high(v) {
-                    return Some(v);
+                    // low material endings with sliders can still be in zugzwang, so deep cutoffs
+                    // there are verified by a search of the same depth in which neither side may
+                    // pass
+                    if !null_move_needs_verification(&position.board, depth) {
+                        return Some(v);
+                    }
+                    self.verifying_null_move = true;
+                    let verified = self.visit_null(position, window, depth - reduction - 1);
+                    self.verifying_null_move = false;
+                    if window.fail_high(verified?) {
+                        return Some(v);
+                    }
                 }
             }
         }
@@ -79,28 +112,43 @@ impl Searcher<'_> {
             entry.map(|e| e.mv),
             window,
             depth,
-            |this, i, mv, new_pos, window| {
-                let extension = match () {
-                    _ if !new_pos.board.checkers().is_empty() => 1,
-                    _ => 0,
-                };
+            |_, i, mv, window| {
+                // futility pruning: near the leaves, a quiet move is unlikely to make up for a
+                // static eval far below alpha. king moves are never pruned, since castling can
+                // give check with the rook
+                let quiet = !position.is_capture(mv)
+                    && mv.promotion.is_none()
+                    && position.board.piece_on(mv.from) != Some(Piece::King);
+                if i == 0
+                    || !quiet
+                    || depth > FP_MAX_DEPTH.get()
+                    || window.lb() <= -Eval::MAX_INCONCLUSIVE
+                    || !position.board.checkers().is_empty()
+                    || gives_check(&position.board, mv)
+                {
+                    return None;
+                }
+                let bound = position.static_eval().raw() as i32 + futility_margin(depth);
+                let limit = Eval::MAX_INCONCLUSIVE.raw() as i32;
+                let bound = Eval::new(bound.clamp(-limit, limit) as i16);
+                window.fail_low(bound).then(|| bound)
+            },
+            |this, i, mv, score, new_pos, window| {
+                let extension = check_extension(new_pos);
 
                 let reduction = match () {
                     _ if extension > 0 => -extension,
+                    _ if score == MoveScore::BadCapture => bad_capture_lmr(depth, i),
                     _ if position.is_capture(mv) => 0,
                     _ if !new_pos.board.checkers().is_empty() => 0,
-                    _ => null_lmr(depth, i),
+                    _ => null_lmr(depth, i, this.state.history.history(position, mv)),
                 };
 
                 if window.lb() >= -Eval::MAX_INCONCLUSIVE && depth - reduction - 1 < 0 {
                     return Some(-Eval::MATE);
                 }
 
-                let mut v = -this.visit_null(new_pos, -window, depth - reduction - 1)?;
-
-                if window.fail_high(v) && reduction > 0 {
-                    v = -this.visit_null(new_pos, -window, depth - 1)?;
-                }
+                let v = this.reduced_search(new_pos, window, depth, reduction)?;
 
                 if window.fail_high(v) {
                     for &mv in &yielded {
@@ -117,3 +165,194 @@ impl Searcher<'_> {
         .map(|(e, _)| e)
     }
 }
+
+/// The re-search logic of [`Searcher::reduced_search`], with `search` returning the score of the
+/// move searched to the given depth.
+fn research_reduced(
+    window: Window,
+    depth: i16,
+    reduction: i16,
+    mut search: impl FnMut(i16) -> Option<Eval>,
+) -> Option<Eval> {
+    let v = search(depth - reduction - 1)?;
+    if reduction > 0 && !window.fail_low(v) {
+        return search(depth - 1);
+    }
+    Some(v)
+}
+
+/// Zugzwang is common in endings where the side to move has no sliders, such as pawn endings,
+/// where passing is often the best move and the null move result can't be trusted.
+fn null_move_allowed(board: &Board) -> bool {
+    let sliders =
+        board.pieces(Piece::Rook) | board.pieces(Piece::Bishop) | board.pieces(Piece::Queen);
+    !(sliders & board.colors(board.side_to_move())).is_empty()
+}
+
+/// Whether a null move cutoff at `depth` must be verified, which is the case when the side to
+/// move has little material besides pawns.
+fn null_move_needs_verification(board: &Board, depth: i16) -> bool {
+    let ours = board.colors(board.side_to_move());
+    let material = 3 * (board.pieces(Piece::Knight) & ours).len()
+        + 3 * (board.pieces(Piece::Bishop) & ours).len()
+        + 5 * (board.pieces(Piece::Rook) & ours).len()
+        + 8 * (board.pieces(Piece::Queen) & ours).len();
+    depth >= NMP_VERIFY_DEPTH.get() && material <= NMP_VERIFY_MATERIAL.get() as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{null_move_allowed, null_move_needs_verification, research_reduced};
+    use crate::search::params::{FP_MAX_DEPTH, NMP_VERIFY_DEPTH};
+    use crate::search::window::Window;
+    use crate::{Eval, Frozenight, TimeConstraint};
+
+    /// Runs [`research_reduced`] with a search that returns `reduced` at the reduced depth and
+    /// `full` at full depth, returning the result and the depths searched.
+    fn searched_depths(
+        window: Window,
+        reduction: i16,
+        reduced: i16,
+        full: i16,
+    ) -> (Eval, Vec<i16>) {
+        let mut depths = vec![];
+        let v = research_reduced(window, 8, reduction, |depth| {
+            depths.push(depth);
+            Some(Eval::new(if depth == 7 { full } else { reduced }))
+        });
+        (v.unwrap(), depths)
+    }
+
+    #[test]
+    fn reduced_search_is_verified_at_full_depth() {
+        // the PV search passes a null window at its lower bound, the null window search its own
+        let pv = Window::null(Window::new(Eval::new(10), Eval::new(50)).lb());
+        let null = Window::null(Eval::new(10));
+        for window in [pv, null] {
+            // beating the lower bound at reduced depth means searching again at full depth
+            assert_eq!(
+                searched_depths(window, 2, 30, -5),
+                (Eval::new(-5), vec![5, 7])
+            );
+            assert_eq!(
+                searched_depths(window, 2, 11, 11),
+                (Eval::new(11), vec![5, 7])
+            );
+            // failing low at reduced depth is trusted
+            assert_eq!(searched_depths(window, 2, 10, 30), (Eval::new(10), vec![5]));
+            assert_eq!(
+                searched_depths(window, 2, -30, 30),
+                (Eval::new(-30), vec![5])
+            );
+            // an unreduced search is never repeated
+            assert_eq!(searched_depths(window, 0, 30, 30), (Eval::new(30), vec![7]));
+            // negative reductions are extensions, which are never repeated either
+            assert_eq!(
+                searched_depths(window, -1, 30, 30),
+                (Eval::new(30), vec![8])
+            );
+        }
+    }
+
+    #[test]
+    fn null_move_is_skipped_without_sliders() {
+        let cases = [
+            ("8/5pk1/6p1/8/8/6P1/5PK1/8 w - - 0 1", false),
+            ("8/5pk1/6p1/8/8/4N1P1/5PK1/8 w - - 0 1", false),
+            // only the side to move's pieces matter
+            ("8/5pk1/6p1/8/3r4/6P1/5PK1/8 w - - 0 1", false),
+            ("8/5pk1/6p1/8/8/6P1/5PK1/R7 w - - 0 1", true),
+            ("8/5pk1/6p1/8/8/6P1/5PK1/B7 w - - 0 1", true),
+        ];
+        for (fen, allowed) in cases {
+            assert_eq!(null_move_allowed(&fen.parse().unwrap()), allowed, "{}", fen);
+        }
+    }
+
+    #[test]
+    fn low_material_cutoffs_are_verified() {
+        let deep = NMP_VERIFY_DEPTH.get();
+        let cases = [
+            // a lone slider, or a slider and a minor piece, can be in zugzwang
+            ("8/5pk1/6p1/8/8/6P1/5PK1/R7 w - - 0 1", true),
+            ("8/5pk1/6p1/8/8/6P1/5PK1/Q7 w - - 0 1", true),
+            ("8/5pk1/6p1/8/8/6P1/4NPK1/R7 w - - 0 1", true),
+            ("8/5pk1/6p1/8/8/6P1/3B1PK1/B7 w - - 0 1", true),
+            // the opponent's material doesn't protect us from zugzwang
+            ("r6q/5pk1/6p1/8/8/6P1/5PK1/R7 w - - 0 1", true),
+            ("8/5pk1/6p1/8/8/6P1/5PK1/RR6 w - - 0 1", false),
+            ("8/5pk1/6p1/8/8/6P1/5PK1/QR6 w - - 0 1", false),
+        ];
+        for (fen, verified) in cases {
+            let board = fen.parse().unwrap();
+            assert_eq!(
+                null_move_needs_verification(&board, deep),
+                verified,
+                "{}",
+                fen
+            );
+            assert!(!null_move_needs_verification(&board, deep - 1), "{}", fen);
+        }
+    }
+
+    /// A pawn ending from the usual null move test positions, where white only wins by putting
+    /// black in zugzwang with g5.
+    #[test]
+    fn pawn_ending_zugzwang_is_found() {
+        let fen = "7k/5K2/5P1p/3p4/6P1/3p4/8/8 w - - 0 1";
+        let mut engine = Frozenight::new(16);
+        engine.set_position(fen.parse().unwrap(), std::iter::empty());
+        let time = TimeConstraint {
+            depth: 10,
+            ..TimeConstraint::INFINITE
+        };
+        let info = engine.search(time, |_| {});
+        assert_eq!(info.best_move.to_string(), "g4g5");
+    }
+
+    /// The zugzwang test position from the Chess Programming Wiki, where white wins with Rf1
+    /// because black's lone rook has no safe move. Without verification the deep null move
+    /// searches conclude black is fine and the rook move is missed.
+    #[test]
+    fn single_slider_zugzwang_is_found() {
+        let fen = "8/8/p1p5/1p5p/1P5p/8/PPP2K1p/4R1rk w - - 0 1";
+        let board = fen.parse().unwrap();
+        // both sides have a lone rook, so deep cutoffs are verified for either of them
+        assert!(null_move_needs_verification(&board, NMP_VERIFY_DEPTH.get()));
+        let mut engine = Frozenight::new(16);
+        engine.set_position(board, std::iter::empty());
+        let time = TimeConstraint {
+            depth: NMP_VERIFY_DEPTH.get() + 2,
+            ..TimeConstraint::INFINITE
+        };
+        let info = engine.search(time, |_| {});
+        assert_eq!(info.best_move.to_string(), "e1f1");
+    }
+
+    /// Positions from Win At Chess whose best move is quiet, which pruning near the leaves must
+    /// not hide.
+    #[test]
+    fn quiet_tactics_are_found() {
+        let suite = [
+            (
+                "2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - 0 1",
+                "g3g6",
+            ),
+            (
+                "5rk1/1ppb3p/p1pb4/6q1/3P1p1r/2P1R2P/PP1BQ1P1/5RKN w - - 0 1",
+                "e3g3",
+            ),
+        ];
+        assert!(FP_MAX_DEPTH.get() > 0, "futility pruning is disabled");
+        for (fen, best) in suite {
+            let mut engine = Frozenight::new(16);
+            engine.set_position(fen.parse().unwrap(), std::iter::empty());
+            let time = TimeConstraint {
+                depth: 9,
+                ..TimeConstraint::INFINITE
+            };
+            let info = engine.search(time, |_| {});
+            assert_eq!(info.best_move.to_string(), best, "{}", fen);
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use cozy_chess::{bitboard, BitBoard, Board, Color, Piece};
+use cozy_chess::{bitboard, BitBoard, Board, Color, Piece, Rank, Square};
 
 use crate::Eval;
 
@@ -24,25 +24,41 @@ const CORNERS: BitBoard = bitboard! {
     X . . . . . . X
 };
 
-pub fn oracle(board: &Board) -> Option<Eval> {
+/// Whether neither side can possibly checkmate, whatever moves are played.
+pub fn is_dead_draw(board: &Board) -> bool {
     let bishops = board.pieces(Piece::Bishop);
     let knights = board.pieces(Piece::Knight);
     let kings = board.pieces(Piece::King);
 
-    // only checking minor piece draws
     if board.occupied() != bishops | knights | kings {
-        return None;
+        return false;
+    }
+
+    // KvK, KBvK, KNvK
+    if board.occupied().len() <= 3 {
+        return true;
     }
 
     // bishops of same color draw
-    if board.occupied().len() - 2 == bishops.len() {
-        if bishops.is_subset(CHECKERBOARD) || bishops.is_subset(!CHECKERBOARD) {
-            return Some(Eval::DRAW);
-        }
+    board.occupied().len() - 2 == bishops.len()
+        && (bishops.is_subset(CHECKERBOARD) || bishops.is_subset(!CHECKERBOARD))
+}
+
+pub fn oracle(board: &Board) -> Option<Eval> {
+    let bishops = board.pieces(Piece::Bishop);
+    let knights = board.pieces(Piece::Knight);
+    let kings = board.pieces(Piece::King);
+
+    if is_dead_draw(board) {
+        return Some(Eval::DRAW);
+    }
+
+    // only checking minor piece draws
+    if board.occupied() != bishops | knights | kings {
+        return None;
     }
 
     match board.occupied().len() {
-        0..=3 => Some(Eval::DRAW), // KvK, KBvK, KNvK
         4 => {
             let minors = bishops | knights;
 
@@ -67,3 +83,142 @@ pub fn oracle(board: &Board) -> Option<Eval> {
         _ => None,
     }
 }
+
+/// A guiding eval for the KPvK, KRvK and KQvK endgames the NNUE tends to meander in, relative to
+/// the side to move.
+pub fn endgame_eval(board: &Board) -> Option<Eval> {
+    let pawns = board.pieces(Piece::Pawn);
+    let majors = board.pieces(Piece::Rook) | board.pieces(Piece::Queen);
+
+    match board.occupied().len() {
+        3 if pawns.len() == 1 => kpk(board),
+        3 if majors.len() == 1 => kxk(board, majors.next_square().unwrap()),
+        _ => None,
+    }
+}
+
+fn kpk(board: &Board) -> Option<Eval> {
+    let pawn = board.pieces(Piece::Pawn).next_square().unwrap();
+    let strong = board.color_on(pawn).unwrap();
+    let strong_king = board.king(strong);
+    let weak_king = board.king(!strong);
+    let strong_to_move = board.side_to_move() == strong;
+
+    // the pawn is lost if the weak side can take it right away
+    if !strong_to_move && distance(weak_king, pawn) == 1 && distance(strong_king, pawn) > 1 {
+        return Some(Eval::DRAW);
+    }
+
+    let rank = pawn.rank().relative_to(strong) as i32;
+    let file = pawn.file() as i32;
+    let promotion = Square::new(pawn.file(), Rank::Eighth.relative_to(strong));
+    let rook_pawn = file == 0 || file == 7;
+
+    let win = Eval::new((500 + 20 * rank as i16) * Eval::CP_SCALE);
+    let win = match strong_to_move {
+        true => win,
+        false => -win,
+    };
+
+    // rule of the square, as long as our own king is not in the way
+    let pawn_moves = (7 - rank).min(5);
+    let king_moves = distance(weak_king, promotion) - !strong_to_move as i32;
+    let blocked =
+        strong_king.file() == pawn.file() && strong_king.rank().relative_to(strong) as i32 > rank;
+    if king_moves > pawn_moves && !blocked {
+        return Some(win);
+    }
+
+    if rook_pawn {
+        if distance(weak_king, promotion) <= 1 {
+            return Some(Eval::DRAW);
+        }
+        return None;
+    }
+
+    // key squares are two ranks ahead of the pawn, and also one rank ahead once it has crossed
+    // the middle of the board
+    let king_rank = strong_king.rank().relative_to(strong) as i32;
+    let king_file = strong_king.file() as i32;
+    let key_rank = match rank {
+        1..=3 => king_rank == rank + 2,
+        4 | 5 => king_rank == rank + 1 || king_rank == rank + 2,
+        _ => false,
+    };
+    if key_rank && (king_file - file).abs() <= 1 {
+        return Some(win);
+    }
+
+    None
+}
+
+fn kxk(board: &Board, piece: Square) -> Option<Eval> {
+    let strong = board.color_on(piece).unwrap();
+    let strong_king = board.king(strong);
+    let weak_king = board.king(!strong);
+    let strong_to_move = board.side_to_move() == strong;
+
+    // the piece is lost if the weak side can take it right away
+    if !strong_to_move && distance(weak_king, piece) == 1 && distance(strong_king, piece) > 1 {
+        return Some(Eval::DRAW);
+    }
+
+    let file = weak_king.file() as i32;
+    let rank = weak_king.rank() as i32;
+    let from_edge = file.min(7 - file).min(rank).min(7 - rank);
+
+    let cp = 1000 + 50 * (3 - from_edge) + 10 * (7 - distance(strong_king, weak_king));
+    let win = Eval::new(cp as i16 * Eval::CP_SCALE);
+    match strong_to_move {
+        true => Some(win),
+        false => Some(-win),
+    }
+}
+
+fn distance(a: Square, b: Square) -> i32 {
+    let files = (a.file() as i32 - b.file() as i32).abs();
+    let ranks = (a.rank() as i32 - b.rank() as i32).abs();
+    files.max(ranks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Frozenight, TimeConstraint};
+
+    fn dead(fen: &str) -> bool {
+        is_dead_draw(&fen.parse().unwrap())
+    }
+
+    fn search(fen: &str) -> Eval {
+        let mut engine = Frozenight::new(1);
+        engine.set_position(fen.parse().unwrap(), std::iter::empty());
+        engine.search(TimeConstraint::fixed_depth(1), |_| {}).eval
+    }
+
+    #[test]
+    fn dead_draws() {
+        assert!(dead("4k3/8/8/8/8/8/8/4K3 w - - 0 1"));
+        assert!(dead("4k3/8/8/8/8/8/8/2B1K3 w - - 0 1"));
+        assert!(dead("4k3/8/8/8/8/8/8/1N2K3 b - - 0 1"));
+        // bishops on dark squares only
+        assert!(dead("4kb2/8/8/8/8/8/8/2B1K3 w - - 0 1"));
+        assert!(dead("4kb2/8/8/8/8/8/8/B1B1K3 w - - 0 1"));
+
+        // bishops on both colors, or knights with other minors, can still mate with help
+        assert!(!dead("2b1k3/8/8/8/8/8/8/2B1K3 w - - 0 1"));
+        assert!(!dead("4k3/8/8/8/8/8/8/1NN1K3 w - - 0 1"));
+        assert!(!dead("1n2k3/8/8/8/8/8/8/2B1K3 w - - 0 1"));
+        assert!(!dead("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1"));
+    }
+
+    #[test]
+    fn king_and_pawn_endings() {
+        // the white king is on a key square of the d-pawn, so white wins with either side to move
+        assert!(search("8/8/3K1k2/8/3P4/8/8/8 w - - 0 1") > Eval::new(300 * Eval::CP_SCALE));
+        assert!(search("8/8/3K1k2/8/3P4/8/8/8 b - - 0 1") < Eval::new(-300 * Eval::CP_SCALE));
+
+        // the black king holds the corner in front of the rook pawn
+        assert_eq!(search("k7/8/8/PK6/8/8/8/8 w - - 0 1"), Eval::DRAW);
+    }
+}
@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use cozy_chess::{Color, Move, Piece, Square};
 
 use crate::position::Position;
@@ -8,16 +10,75 @@ use super::{Searcher, INVALID_MOVE};
 pub const CONTINUE: bool = false;
 pub const BREAK: bool = true;
 
+/// The ordering stage a move was tried in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MoveScore {
+    Hash,
+    GoodCapture,
+    Killer,
+    Quiet,
+    BadCapture,
+    Underpromotion,
+}
+
 impl Searcher<'_> {
     pub fn visit_moves(
         &mut self,
         position: &Position,
         hashmove: Option<Move>,
-        mut search: impl FnMut(&mut Searcher, Move) -> Option<bool>,
+        search: impl FnMut(&mut Searcher, Move, MoveScore) -> Option<bool>,
+    ) -> Option<()> {
+        if position.ply == 0 && !self.root_order.is_empty() {
+            self.visit_root_moves(position, hashmove, search)
+        } else {
+            self.visit_staged_moves(position, hashmove, search)
+        }
+    }
+
+    /// Visits the root moves in order of their scores in the previous iteration, which are a
+    /// better guide than history since every root move is searched anyway. The hash move is
+    /// still tried first, as it can be from an aspiration re-search of the current iteration.
+    fn visit_root_moves(
+        &mut self,
+        position: &Position,
+        hashmove: Option<Move>,
+        mut search: impl FnMut(&mut Searcher, Move, MoveScore) -> Option<bool>,
+    ) -> Option<()> {
+        let mut moves = Vec::with_capacity(64);
+        self.visit_staged_moves(position, hashmove, |_, mv, score| {
+            moves.push((mv, score));
+            Some(CONTINUE)
+        })?;
+
+        let first = match moves.first() {
+            Some(&(_, MoveScore::Hash)) => 1,
+            _ => 0,
+        };
+        let ranks: HashMap<Move, usize> = self
+            .root_order
+            .iter()
+            .enumerate()
+            .map(|(i, &mv)| (mv, i))
+            .collect();
+        moves[first..].sort_by_key(|(mv, _)| ranks.get(mv).copied().unwrap_or(usize::MAX));
+
+        for (mv, score) in moves {
+            if search(self, mv, score)? {
+                break;
+            }
+        }
+        Some(())
+    }
+
+    fn visit_staged_moves(
+        &mut self,
+        position: &Position,
+        hashmove: Option<Move>,
+        mut search: impl FnMut(&mut Searcher, Move, MoveScore) -> Option<bool>,
     ) -> Option<()> {
         // Hashmove
         if let Some(mv) = hashmove {
-            if search(self, mv)? {
+            if search(self, mv, MoveScore::Hash)? {
                 return Some(());
             }
         }
@@ -42,7 +103,7 @@ impl Searcher<'_> {
                 }
 
                 if position.is_capture(mv) {
-                    let victim = position.board.piece_on(mv.to).unwrap();
+                    let victim = position.board.piece_on(mv.to).unwrap_or(Piece::Pawn);
                     let mvv_lva = 8 * victim as i32 - mvs.piece as i32 + 8;
                     captures.push((mv, static_exchange_eval(&position.board, mv) + mvv_lva));
                 } else if mv == killer {
@@ -67,7 +128,12 @@ impl Searcher<'_> {
             if captures[index].1 < 0 {
                 break;
             }
-            if search(self, captures.swap_remove(index).0)? {
+            let (mv, _) = captures.swap_remove(index);
+            let score = match mv == killer {
+                true => MoveScore::Killer,
+                false => MoveScore::GoodCapture,
+            };
+            if search(self, mv, score)? {
                 return Some(());
             }
         }
@@ -91,7 +157,7 @@ impl Searcher<'_> {
                 }
             }
 
-            if search(self, quiets.swap_remove(index).0)? {
+            if search(self, quiets.swap_remove(index).0, MoveScore::Quiet)? {
                 return Some(());
             }
         }
@@ -105,14 +171,14 @@ impl Searcher<'_> {
                 }
             }
 
-            if search(self, captures.swap_remove(index).0)? {
+            if search(self, captures.swap_remove(index).0, MoveScore::BadCapture)? {
                 return Some(());
             }
         }
 
         // Iterate underpromotions
         while let Some(mv) = underpromotions.pop() {
-            if search(self, mv)? {
+            if search(self, mv, MoveScore::Underpromotion)? {
                 return Some(());
             }
         }
@@ -136,6 +202,19 @@ impl OrderingState {
         }
     }
 
+    /// Resets the state to that of [`new`](Self::new) without building a new one, since the
+    /// history tables are large enough that constructing them shows up when searching many
+    /// unrelated positions.
+    pub fn clear(&mut self) {
+        for counter in (&mut self.piece_to_sq).into_iter().flatten().flatten() {
+            *counter = HistoryCounter::default();
+        }
+        for counter in (&mut self.from_sq_to_sq).into_iter().flatten().flatten() {
+            *counter = HistoryCounter::default();
+        }
+        self.killers.fill(INVALID_MOVE);
+    }
+
     pub fn decay(&mut self) {
         for counter in (&mut self.piece_to_sq).into_iter().flatten().flatten() {
             counter.decay(64);
@@ -145,6 +224,20 @@ impl OrderingState {
         }
     }
 
+    /// Moves each history counter halfway towards the corresponding counter in `other`.
+    pub fn blend(&mut self, other: &OrderingState) {
+        let piece_to = (&mut self.piece_to_sq).into_iter().flatten().flatten();
+        let other_piece_to = (&other.piece_to_sq).into_iter().flatten().flatten();
+        for (counter, other) in piece_to.zip(other_piece_to) {
+            counter.blend(other);
+        }
+        let from_to = (&mut self.from_sq_to_sq).into_iter().flatten().flatten();
+        let other_from_to = (&other.from_sq_to_sq).into_iter().flatten().flatten();
+        for (counter, other) in from_to.zip(other_from_to) {
+            counter.blend(other);
+        }
+    }
+
     pub fn caused_cutoff(&mut self, pos: &Position, mv: Move, depth: i16) {
         let stm = pos.board.side_to_move();
         let piece = pos.board.piece_on(mv.from).unwrap();
@@ -171,6 +264,14 @@ impl OrderingState {
         }
     }
 
+    /// The history score of a quiet move, where higher is better and a move without any history
+    /// scores 0.
+    pub fn history(&self, pos: &Position, mv: Move) -> i32 {
+        let piece = pos.board.piece_on(mv.from).unwrap();
+        let neutral = 2 * HistoryCounter::default().value;
+        self.rank(piece, mv, pos.board.side_to_move()) - neutral
+    }
+
     fn rank(&self, piece: Piece, mv: Move, stm: Color) -> i32 {
         let piece_to = self.piece_to_sq[stm][piece][mv.to].value;
         let from_to = self.from_sq_to_sq[stm][mv.from][mv.to].value;
@@ -185,6 +286,12 @@ impl OrderingState {
     }
 }
 
+impl Default for OrderingState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 struct HistoryCounter {
     value: i32,
@@ -205,6 +312,10 @@ impl HistoryCounter {
         self.value -= self.value / self.count;
     }
 
+    fn blend(&mut self, other: &HistoryCounter) {
+        self.value += (other.value - self.value) / 2;
+    }
+
     #[inline(always)]
     fn decay(&mut self, factor: i32) {
         self.count = 1.max(self.count / factor);
@@ -248,6 +359,16 @@ macro_rules! tables {
                 }
             }
 
+            impl<'a, T> IntoIterator for &'a $table<T> {
+                type Item = &'a T;
+                type IntoIter = std::slice::Iter<'a, T>;
+
+                #[inline(always)]
+                fn into_iter(self) -> Self::IntoIter {
+                    self.0.iter()
+                }
+            }
+
             impl<'a, T> IntoIterator for &'a mut $table<T> {
                 type Item = &'a mut T;
                 type IntoIter = std::slice::IterMut<'a, T>;
@@ -266,3 +387,34 @@ tables! {
     PieceTable: Piece;
     SquareTable: Square;
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+
+    use super::*;
+    use crate::{Eval, Frozenight};
+
+    #[test]
+    fn previous_best_move_is_tried_first() {
+        let mut engine = Frozenight::new(1);
+        let fen = "r1bqkb1r/pppp1ppp/2n2n2/4p2Q/2B1P3/8/PPPP1PPP/RNB1K1NR w KQkq - 4 4";
+        engine.set_position(fen.parse().unwrap(), std::iter::empty());
+        engine.with_searcher(u64::MAX, &AtomicBool::new(false), None, |mut searcher| {
+            let root = Position::from_root(searcher.root.clone());
+            let mut eval = Eval::DRAW;
+            for depth in 1..6 {
+                let (e, best) = searcher.search(depth, eval).unwrap();
+                eval = e;
+
+                let mut order = vec![];
+                searcher.visit_moves(&root, None, |_, mv, _| {
+                    order.push(mv);
+                    Some(CONTINUE)
+                });
+                assert_eq!(order[0], best, "depth {}", depth);
+                assert_eq!(order, searcher.root_order, "depth {}", depth);
+            }
+        });
+    }
+}
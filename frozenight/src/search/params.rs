@@ -84,14 +84,24 @@ macro_rules! tweakables {
 }
 
 tweakables! {
+    ASP_WINDOW: 1..=2000 = 500;
+    ASP_GROWTH: 17..=64 = 32;
+
     RFP_MARGIN_M: 0..=5000 = 255;
     RFP_MARGIN_C: 0..=5000 = 11;
     RFP_MAX_DEPTH: 1..=20 = 8;
 
+    FP_MARGIN_M: 0..=5000 = 500;
+    FP_MARGIN_C: 0..=5000 = 250;
+    FP_MAX_DEPTH: 0..=20 = 3;
+
     NMP_MIN_DEPTH: 1..=20 = 1;
     NMP_REDUCTION_M: 0..=128 = 77;
     NMP_REDUCTION_C: 0..=1024 = 38;
     NMP_REDUCTION_MARGIN: 1..=2048 = 625;
+    NMP_REDUCTION_MARGIN_MAX: 0..=16 = 3;
+    NMP_VERIFY_DEPTH: 1..=64 = 12;
+    NMP_VERIFY_MATERIAL: 0..=62 = 8;
 
     LMR_I1_M: 0..=256 = 92;
     LMR_I1_C: 0..=1024 = 15;
@@ -100,6 +110,53 @@ tweakables! {
     LMR_D_M: 0..=256 = 28;
     LMR_D_C: 0..=1024 = 8;
     PV_LMR_FACTOR: 0..=128 = 74;
+    LMR_HISTORY: 0..=64 = 8;
+    BAD_CAPTURE_LMR_FACTOR: 0..=128 = 64;
+
+    MAX_CHECK_EXTENSIONS: 0..=64 = 16;
+    PV_EXT_PLY_INTERVAL: 1..=16 = 4;
+    PV_EXT_DEPTH_RATIO: 1..=16 = 2;
+
+    // off until multi-thread scaling is measured; the merge takes a lock every iteration
+    HISTORY_MERGE: 0..=1 = 0;
+
+    CONTEMPT_OPENING: 0..=500 = 0;
+    CONTEMPT_ENDGAME: 0..=500 = 0;
+
+    NNUE_OUTPUT_SCALE: 1..=64 = 8;
+
+    // off until a bench run shows the extra qsearch nodes pay for themselves
+    QSEARCH_CHECKS: 0..=1 = 0;
+    // off until a bench run confirms node counts drop without losing tactics
+    DELTA_PRUNING: 0..=1 = 0;
+    DELTA_PRUNING_MARGIN: 0..=5000 = 1000;
+
+    SEE_PAWN: 1..=1000 = 100;
+    SEE_KNIGHT: 1..=2000 = 300;
+    SEE_BISHOP: 1..=2000 = 325;
+    SEE_ROOK: 1..=3000 = 500;
+    SEE_QUEEN: 1..=5000 = 900;
+}
+
+/// Formats every parameter as an OpenBench SPSA config line.
+///
+/// Each line is `name, int, default, min, max, step, R_end`, where the step is a twentieth of the
+/// parameter's range.
+#[cfg(feature = "tweakable")]
+pub fn dump_spsa_config() -> String {
+    let mut config = String::new();
+    for param in all_parameters() {
+        let step = ((param.max - param.min) as f64 / 20.0).max(0.5);
+        config += &format!(
+            "{}, int, {}, {}, {}, {}, 0.002\n",
+            param.name(),
+            param.default,
+            param.min,
+            param.max,
+            step
+        );
+    }
+    config
 }
 
 #[inline(always)]
@@ -107,20 +164,73 @@ pub fn rfp_margin(depth: i16) -> i16 {
     RFP_MARGIN_M.get() * depth + RFP_MARGIN_C.get()
 }
 
+/// Computed in `i32`, since the largest tweakable values overflow an `i16`.
+#[inline(always)]
+pub fn futility_margin(depth: i16) -> i32 {
+    FP_MARGIN_M.get() as i32 * depth as i32 + FP_MARGIN_C.get() as i32
+}
+
+/// The null move reduction grows with depth, and by another ply for every margin the static eval
+/// exceeds beta by, up to a cap so that a huge eval can't reduce the verification to nothing.
 #[inline(always)]
 pub fn nmp_reduction(depth: i16, eval_over_beta: i32) -> i16 {
     let base = trunc(linear(depth, NMP_REDUCTION_M.get(), NMP_REDUCTION_C.get()));
-    base + (eval_over_beta / NMP_REDUCTION_MARGIN.get() as i32) as i16
+    let margins = eval_over_beta / NMP_REDUCTION_MARGIN.get() as i32;
+    base + margins.min(NMP_REDUCTION_MARGIN_MAX.get() as i32) as i16
+}
+
+#[inline(always)]
+pub fn null_lmr(depth: i16, movenum: usize, history: i32) -> i16 {
+    trunc(raw_lmr(depth, movenum as i16) - history_effect(history)).max(0)
+}
+
+#[inline(always)]
+pub fn pv_lmr(depth: i16, movenum: usize, history: i32) -> i16 {
+    let raw = raw_lmr(depth, movenum as i16) - history_effect(history);
+    trunc(raw * PV_LMR_FACTOR.get() as i32 / 128).max(0)
 }
 
+/// Whether a PV node whose hash move comes from an exact entry is searched a ply deeper. This
+/// spends extra effort on the expected principal variation every few plies near the root, where
+/// a better PV pays off the most.
+///
+/// The extension is only given while `ply * ratio < depth`. Descending a ply costs a ply of depth,
+/// which cancels out the extension, so the depth never grows along a line while the ply does and
+/// the extensions stop once `ply * ratio` reaches the depth.
 #[inline(always)]
-pub fn null_lmr(depth: i16, movenum: usize) -> i16 {
-    trunc(raw_lmr(depth, movenum as i16))
+pub fn pv_extension(ply: u16, depth: i16) -> bool {
+    ply % PV_EXT_PLY_INTERVAL.get() as u16 == 0
+        && (ply as i32 * PV_EXT_DEPTH_RATIO.get() as i32) < depth as i32
 }
 
+/// How much worse than an even position a draw is for the side to move at the root, tapered by
+/// the [`game_phase`](crate::game_phase) from the opening value to the endgame value. Drawn
+/// endings are often the best either side can do, so avoiding them should cost less.
 #[inline(always)]
-pub fn pv_lmr(depth: i16, movenum: usize) -> i16 {
-    trunc(raw_lmr(depth, movenum as i16) * PV_LMR_FACTOR.get() as i32 / 128)
+pub fn contempt(phase: u32) -> i16 {
+    taper(CONTEMPT_OPENING.get(), CONTEMPT_ENDGAME.get(), phase)
+}
+
+/// Interpolates between `opening` and `endgame` by a [`game_phase`](crate::game_phase).
+#[inline(always)]
+fn taper(opening: i16, endgame: i16, phase: u32) -> i16 {
+    let phase = phase as i32;
+    ((opening as i32 * (256 - phase) + endgame as i32 * phase) / 256) as i16
+}
+
+/// Losing captures are reduced by a fraction of the reduction a quiet move would get, but never
+/// so far that they are pruned outright, so a sound sacrifice can still be found by the re-search.
+#[inline(always)]
+pub fn bad_capture_lmr(depth: i16, movenum: usize) -> i16 {
+    let raw = raw_lmr(depth, movenum as i16);
+    trunc(raw * BAD_CAPTURE_LMR_FACTOR.get() as i32 / 128)
+        .min(depth - 1)
+        .max(0)
+}
+
+#[inline(always)]
+fn history_effect(history: i32) -> i32 {
+    (history as i64 * LMR_HISTORY.get() as i64 / 1_000_000) as i32
 }
 
 #[inline(always)]
@@ -139,3 +249,62 @@ fn linear(x: i16, m: i16, c: i16) -> i32 {
 fn trunc(v: i32) -> i16 {
     (v / 128) as i16
 }
+
+#[cfg(test)]
+mod tests {
+    use cozy_chess::Board;
+
+    use super::*;
+    use crate::game_phase;
+
+    #[test]
+    fn contempt_decreases_as_material_comes_off() {
+        // the queens, then the rooks, then the minor pieces come off
+        let fens = [
+            "r1bqkb1r/pppp1ppp/2n2n2/4p3/4P3/2N2N2/PPPP1PPP/R1BQKB1R w KQkq - 0 1",
+            "r1b1kb1r/pppp1ppp/2n2n2/4p3/4P3/2N2N2/PPPP1PPP/R1B1KB1R w KQkq - 0 1",
+            "2b1kb2/pppp1ppp/2n2n2/4p3/4P3/2N2N2/PPPP1PPP/2B1KB2 w - - 0 1",
+            "4k3/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/4K3 w - - 0 1",
+            "4k3/8/8/8/8/8/8/4K3 w - - 0 1",
+        ];
+        let phases: Vec<_> = fens
+            .iter()
+            .map(|fen| game_phase(&fen.parse::<Board>().unwrap()))
+            .collect();
+        assert_eq!(taper(50, 10, phases[0]), 50);
+        assert_eq!(taper(50, 10, phases[4]), 10);
+        for pair in phases.windows(2) {
+            assert!(taper(50, 10, pair[0]) > taper(50, 10, pair[1]));
+        }
+
+        // the defaults are both zero, so only check that contempt goes through the same taper
+        for &phase in &phases {
+            let expected = taper(CONTEMPT_OPENING.get(), CONTEMPT_ENDGAME.get(), phase);
+            assert_eq!(contempt(phase), expected);
+        }
+    }
+
+    #[test]
+    fn pv_extension_never_grows_depth_unboundedly() {
+        for root_depth in 1..=128 {
+            // follow a line where every node gets the extension whenever it is allowed
+            let mut ply = 0;
+            let mut depth = root_depth;
+            let mut deepest = depth;
+            while depth > 0 {
+                if pv_extension(ply, depth) {
+                    depth += 1;
+                    deepest = deepest.max(depth);
+                }
+                ply += 1;
+                depth -= 1;
+                assert!(
+                    ply <= 2 * root_depth as u16,
+                    "line from depth {} never ends",
+                    root_depth
+                );
+            }
+            assert!(deepest <= root_depth + 1);
+        }
+    }
+}
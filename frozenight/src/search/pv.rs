@@ -1,12 +1,15 @@
+use std::sync::atomic::Ordering;
+
 use cozy_chess::Move;
 
 use crate::position::Position;
 use crate::tt::NodeKind;
 use crate::Eval;
 
+use super::ordering::MoveScore;
 use super::params::*;
 use super::window::Window;
-use super::Searcher;
+use super::{check_extension, Searcher};
 
 impl Searcher<'_> {
     pub fn pv_search(
@@ -15,7 +18,14 @@ impl Searcher<'_> {
         window: Window,
         mut depth: i16,
     ) -> Option<(Eval, Move)> {
-        let hashmove = match self.shared.tt.get(position) {
+        // a cutoff at the root reports the move, so it must not be one the root is not allowed to
+        // play
+        let entry = self
+            .shared
+            .tt
+            .get(position)
+            .filter(|entry| position.ply > 0 || !self.excluded.contains(&entry.mv));
+        let hashmove = match entry {
             None => None,
             Some(entry) => {
                 if entry.depth >= depth {
@@ -41,53 +51,66 @@ impl Searcher<'_> {
                     // internal iterative deepening
                     Some(self.pv_search(position, window, depth - 2)?.1)
                 } else {
-                    if entry.kind == NodeKind::Exact {
-                        if position.ply % 4 == 0 && position.ply as i16 * 2 < depth {
-                            depth += 1;
-                        }
+                    if entry.kind == NodeKind::Exact && pv_extension(position.ply, depth) {
+                        depth += 1;
                     }
                     Some(entry.mv)
                 }
             }
         };
 
+        self.stats
+            .effective_depth
+            .fetch_max(position.ply as i16 + depth, Ordering::Relaxed);
+
+        trace!(
+            position,
+            "depth {} window [{}, {}] static eval {} hash move {:?}",
+            depth,
+            window.lb(),
+            window.ub(),
+            position.static_eval(),
+            hashmove,
+        );
+
         self.search_moves(
             position,
             hashmove,
             window,
             depth,
-            |this, i, mv, new_pos, window| {
-                let extension = match () {
-                    _ if !new_pos.board.checkers().is_empty() => 1,
-                    _ => 0,
-                };
+            |_, _, _, _| None,
+            |this, i, mv, score, new_pos, window| {
+                let extension = check_extension(new_pos);
 
                 if i == 0 {
+                    trace!(position, "  {} ({:?}): extension {}", mv, score, extension);
                     // First move; search as PV node
                     return Some(-this.visit_pv(new_pos, -window, depth + extension - 1)?);
                 }
 
                 let reduction = match () {
                     _ if extension > 0 => -extension,
+                    _ if score == MoveScore::BadCapture => bad_capture_lmr(depth, i),
                     _ if position.is_capture(mv) => 0,
                     _ if !new_pos.board.checkers().is_empty() => 0,
-                    _ => pv_lmr(depth, i),
+                    _ => pv_lmr(depth, i, this.state.history.history(position, mv)),
                 };
+                trace!(
+                    position,
+                    "  {} ({:?}): extension {} reduction {}",
+                    mv,
+                    score,
+                    extension,
+                    reduction
+                );
 
-                let mut v =
-                    -this.visit_null(new_pos, -Window::null(window.lb()), depth - reduction - 1)?;
+                let v =
+                    this.reduced_search(new_pos, Window::null(window.lb()), depth, reduction)?;
 
                 if window.fail_low(v) {
                     return Some(v);
                 }
 
-                if reduction > 0 {
-                    v = -this.visit_null(new_pos, -Window::null(window.lb()), depth - 1)?;
-                    if window.fail_low(v) {
-                        return Some(v);
-                    }
-                }
-
                 if window.fail_high(v) {
                     // null window search search returned a lower bound that exceeds beta,
                     // so there's no need to re-search
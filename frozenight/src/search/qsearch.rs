@@ -1,18 +1,37 @@
 use std::sync::atomic::Ordering;
 
-use cozy_chess::{get_king_moves, BitBoard, Move, Piece, Rank};
+use cozy_chess::{
+    get_bishop_moves, get_king_moves, get_knight_moves, get_pawn_attacks, get_rook_moves, BitBoard,
+    Board, Move, Piece, Rank, Square,
+};
 
 use crate::position::Position;
 use crate::tt::{NodeKind, TableEntry};
 use crate::Eval;
 
-use super::see::static_exchange_eval;
+use super::oracle::endgame_eval;
+use super::params::{DELTA_PRUNING, DELTA_PRUNING_MARGIN, QSEARCH_CHECKS};
+use super::see::{piece_value, quiet_exchange_eval, static_exchange_eval};
 use super::window::Window;
 use super::{Searcher, INVALID_MOVE};
 
 impl Searcher<'_> {
-    pub fn qsearch(&mut self, position: &Position, orig_window: Window) -> Eval {
+    pub fn qsearch(&mut self, position: &Position, window: Window) -> Eval {
+        self.qsearch_impl(position, window, QSEARCH_CHECKS.get() != 0)
+    }
+
+    /// If `quiet_checks` is set, safe quiet moves that give check are searched along with
+    /// captures. This is only done at the first ply of qsearch so that it always terminates.
+    fn qsearch_impl(
+        &mut self,
+        position: &Position,
+        orig_window: Window,
+        quiet_checks: bool,
+    ) -> Eval {
         self.stats.nodes.fetch_add(1, Ordering::Relaxed);
+        self.stats
+            .selective_depth
+            .fetch_max(position.ply as i16, Ordering::Relaxed);
 
         let in_check = !position.board.checkers().is_empty();
         let us = position.board.side_to_move();
@@ -29,7 +48,7 @@ impl Searcher<'_> {
             permitted = BitBoard::FULL;
             do_for = BitBoard::FULL;
         } else {
-            best = position.static_eval();
+            best = endgame_eval(&position.board).unwrap_or_else(|| position.static_eval());
             permitted = position.board.colors(!us);
             do_for = !king.bitboard();
         }
@@ -60,6 +79,26 @@ impl Searcher<'_> {
         position.board.generate_moves_for(do_for, |mut mvs| {
             let promo =
                 mvs.piece == Piece::Pawn && mvs.from.rank() == Rank::Seventh.relative_to(us);
+            if quiet_checks && !in_check && !promo {
+                let mut quiet = mvs.to & !position.board.occupied();
+                if mvs.piece == Piece::Pawn {
+                    // a diagonal pawn move to an empty square is an en passant capture
+                    quiet &= !get_pawn_attacks(mvs.from, us);
+                }
+                for to in quiet {
+                    let mv = Move {
+                        from: mvs.from,
+                        to,
+                        promotion: None,
+                    };
+                    if !position.is_capture(mv)
+                        && gives_check(&position.board, mv)
+                        && quiet_exchange_eval(&position.board, mv) >= 0
+                    {
+                        moves.push((mv, -1));
+                    }
+                }
+            }
             if !promo {
                 mvs.to &= permitted;
             }
@@ -71,7 +110,17 @@ impl Searcher<'_> {
                     promotion: promo.then(|| Piece::Queen),
                 };
                 if position.is_capture(mv) {
-                    let victim = position.board.piece_on(mv.to).unwrap();
+                    let victim = position.board.piece_on(mv.to).unwrap_or(Piece::Pawn);
+                    // delta pruning: skip captures that can't raise alpha even with a margin
+                    let gain = piece_value(victim) * Eval::CP_SCALE as i32
+                        + DELTA_PRUNING_MARGIN.get() as i32;
+                    if DELTA_PRUNING.get() != 0
+                        && !in_check
+                        && !promo
+                        && best.raw() as i32 + gain <= window.lb().raw() as i32
+                    {
+                        continue;
+                    }
                     let mvv_lva = 8 * victim as i32 - mvs.piece as i32 + 8;
                     let see = static_exchange_eval(&position.board, mv);
                     if see >= 0 || in_check {
@@ -131,7 +180,7 @@ impl Searcher<'_> {
             }
             let mv = moves.swap_remove(index).0;
 
-            let v = -self.qsearch(&position.play_move(mv, &self.shared.tt), -window);
+            let v = -self.qsearch_impl(&position.play_move(mv, &self.shared.tt), -window, false);
             if window.fail_high(v) {
                 self.shared.tt.store(
                     position,
@@ -170,3 +219,100 @@ impl Searcher<'_> {
         best
     }
 }
+
+/// Whether the non-king move `mv` checks the opponent, either with the moved piece or by
+/// uncovering one of our sliders. This avoids playing the move, which is too slow to do for every
+/// quiet move.
+pub fn gives_check(board: &Board, mv: Move) -> bool {
+    let us = board.side_to_move();
+    let their_king = board.king(!us);
+    let moved = board.piece_on(mv.from);
+    let mut occupied = board.occupied() ^ mv.from.bitboard() | mv.to.bitboard();
+    if moved == Some(Piece::Pawn) && mv.from.file() != mv.to.file() && !board.occupied().has(mv.to)
+    {
+        // en passant also uncovers the square of the captured pawn
+        occupied ^= Square::new(mv.to.file(), mv.from.rank()).bitboard();
+    }
+
+    let direct = match mv.promotion.or(moved) {
+        Some(Piece::Pawn) => get_pawn_attacks(mv.to, us),
+        Some(Piece::Knight) => get_knight_moves(mv.to),
+        Some(Piece::Bishop) => get_bishop_moves(mv.to, occupied),
+        Some(Piece::Rook) => get_rook_moves(mv.to, occupied),
+        Some(Piece::Queen) => get_bishop_moves(mv.to, occupied) | get_rook_moves(mv.to, occupied),
+        _ => BitBoard::EMPTY,
+    };
+    if direct.has(their_king) {
+        return true;
+    }
+
+    // the moved piece was handled above, so leave it out of the sliders that could be uncovered
+    let ours = board.colors(us) ^ mv.from.bitboard();
+    let queens = board.pieces(Piece::Queen);
+    let diagonal = (board.pieces(Piece::Bishop) | queens) & ours;
+    let orthogonal = (board.pieces(Piece::Rook) | queens) & ours;
+    !(get_bishop_moves(their_king, occupied) & diagonal).is_empty()
+        || !(get_rook_moves(their_king, occupied) & orthogonal).is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+
+    use super::*;
+    use crate::Frozenight;
+
+    fn qsearch(fen: &str, quiet_checks: bool) -> Eval {
+        let mut engine = Frozenight::new(1);
+        engine.set_position(fen.parse().unwrap(), std::iter::empty());
+        engine.with_searcher(u64::MAX, &AtomicBool::new(false), None, |mut searcher| {
+            let position = Position::from_root(searcher.root.clone());
+            searcher.qsearch_impl(&position, Window::default(), quiet_checks)
+        })
+    }
+
+    #[test]
+    fn quiet_check_wins_material() {
+        // Nf3+ forks the king and the queen, which captures can't find
+        let fen = "6k1/5ppp/8/4n3/3Q4/8/PPP2P1P/6K1 b - - 0 1";
+        assert!(qsearch(fen, true) > Eval::DRAW);
+        assert!(qsearch(fen, false) < Eval::DRAW);
+    }
+
+    #[test]
+    fn gives_check_matches_playing_the_move() {
+        let fens = [
+            "6k1/5ppp/8/4n3/3Q4/8/PPP2P1P/6K1 b - - 0 1",
+            "4k3/8/8/4N3/8/8/8/4R1K1 w - - 0 1",
+            "4k3/8/8/8/8/8/3P4/3QK2R w K - 0 1",
+            "8/8/1k6/8/3P4/8/5PB1/4K2R w K - 0 1",
+            // promotions check with the promoted piece
+            "3k4/1P6/8/8/8/8/8/4K3 w - - 0 1",
+            "8/1P6/3k4/8/8/8/8/4K3 w - - 0 1",
+            // en passant uncovers the bishop's diagonal through the captured pawn
+            "6k1/8/8/3pP3/8/1B6/8/4K3 w - d6 0 1",
+            // and the rook's rank, which both pawns leave
+            "8/8/8/R2pP2k/8/8/8/4K3 w - d6 0 1",
+        ];
+        for fen in fens {
+            let board: Board = fen.parse().unwrap();
+            board.generate_moves(|mvs| {
+                let piece = mvs.piece;
+                for mv in mvs {
+                    if piece == Piece::King || board.occupied().has(mv.to) {
+                        continue;
+                    }
+                    let mut after = board.clone();
+                    after.play_unchecked(mv);
+                    assert_eq!(
+                        gives_check(&board, mv),
+                        !after.checkers().is_empty(),
+                        "{}",
+                        mv
+                    );
+                }
+                false
+            });
+        }
+    }
+}
@@ -3,20 +3,60 @@ use cozy_chess::{
     Board, Move, Piece, Square,
 };
 
-const VALUES: [i32; Piece::NUM] = [100, 300, 325, 500, 900, 9999999];
+use super::params::{SEE_BISHOP, SEE_KNIGHT, SEE_PAWN, SEE_QUEEN, SEE_ROOK};
+
+/// Far more than any exchange can win, so that a king can never recapture onto a defended square.
+const KING_VALUE: i32 = 9999999;
+
+#[inline(always)]
+pub fn piece_value(piece: Piece) -> i32 {
+    match piece {
+        Piece::Pawn => SEE_PAWN.get() as i32,
+        Piece::Knight => SEE_KNIGHT.get() as i32,
+        Piece::Bishop => SEE_BISHOP.get() as i32,
+        Piece::Rook => SEE_ROOK.get() as i32,
+        Piece::Queen => SEE_QUEEN.get() as i32,
+        Piece::King => KING_VALUE,
+    }
+}
 
 pub fn static_exchange_eval(board: &Board, capture: Move) -> i32 {
+    exchange_eval(board, capture, piece_value)
+}
+
+/// The material the side to move can expect to lose by moving a piece to an empty square, as a
+/// non-positive number.
+pub fn quiet_exchange_eval(board: &Board, mv: Move) -> i32 {
+    let occupied = board.occupied() & !mv.from.bitboard();
+    -see_impl(
+        board,
+        mv.to,
+        board.piece_on(mv.from).unwrap(),
+        occupied,
+        piece_value,
+    )
+}
+
+fn exchange_eval(board: &Board, capture: Move, piece_value: impl Fn(Piece) -> i32) -> i32 {
     let occupied = board.occupied() & !capture.from.bitboard();
-    VALUES[board.piece_on(capture.to).unwrap() as usize]
+    // the target square of an en passant capture is empty
+    piece_value(board.piece_on(capture.to).unwrap_or(Piece::Pawn))
         - see_impl(
             board,
             capture.to,
             board.piece_on(capture.from).unwrap(),
             occupied,
+            piece_value,
         )
 }
 
-fn see_impl(board: &Board, sq: Square, mut piece: Piece, mut occupied: BitBoard) -> i32 {
+fn see_impl(
+    board: &Board,
+    sq: Square,
+    mut piece: Piece,
+    mut occupied: BitBoard,
+    piece_value: impl Fn(Piece) -> i32,
+) -> i32 {
     let mut stm = board.side_to_move();
     let mut bishop_attacks = get_bishop_moves(sq, occupied);
     let mut rook_attacks = get_rook_moves(sq, occupied);
@@ -33,7 +73,7 @@ fn see_impl(board: &Board, sq: Square, mut piece: Piece, mut occupied: BitBoard)
             (get_pawn_attacks(sq, !stm) & board.pieces(Piece::Pawn) & movable).next_square()
         {
             // Pawn
-            gains[i] = VALUES[piece as usize];
+            gains[i] = piece_value(piece);
             if piece == Piece::King {
                 break;
             }
@@ -44,7 +84,7 @@ fn see_impl(board: &Board, sq: Square, mut piece: Piece, mut occupied: BitBoard)
             (get_knight_moves(sq) & board.pieces(Piece::Knight) & movable).next_square()
         {
             // Knight
-            gains[i] = VALUES[piece as usize];
+            gains[i] = piece_value(piece);
             if piece == Piece::King {
                 break;
             }
@@ -54,7 +94,7 @@ fn see_impl(board: &Board, sq: Square, mut piece: Piece, mut occupied: BitBoard)
             (bishop_attacks & board.pieces(Piece::Bishop) & movable).next_square()
         {
             // Bishop
-            gains[i] = VALUES[piece as usize];
+            gains[i] = piece_value(piece);
             if piece == Piece::King {
                 break;
             }
@@ -65,7 +105,7 @@ fn see_impl(board: &Board, sq: Square, mut piece: Piece, mut occupied: BitBoard)
             (rook_attacks & board.pieces(Piece::Rook) & movable).next_square()
         {
             // Rook
-            gains[i] = VALUES[piece as usize];
+            gains[i] = piece_value(piece);
             if piece == Piece::King {
                 break;
             }
@@ -76,7 +116,7 @@ fn see_impl(board: &Board, sq: Square, mut piece: Piece, mut occupied: BitBoard)
             ((rook_attacks | bishop_attacks) & board.pieces(Piece::Queen) & movable).next_square()
         {
             // Queen
-            gains[i] = VALUES[piece as usize];
+            gains[i] = piece_value(piece);
             if piece == Piece::King {
                 break;
             }
@@ -89,7 +129,7 @@ fn see_impl(board: &Board, sq: Square, mut piece: Piece, mut occupied: BitBoard)
             }
         } else if get_king_moves(sq).has(board.king(stm)) {
             // King
-            gains[i] = VALUES[piece as usize];
+            gains[i] = piece_value(piece);
             if piece == Piece::King {
                 break;
             }
@@ -108,3 +148,39 @@ fn see_impl(board: &Board, sq: Square, mut piece: Piece, mut occupied: BitBoard)
 
     value
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn see_sign_is_stable_under_scaling() {
+        let fens = [
+            "1k1r4/1pp4p/p7/4p3/8/P5P1/1PP4P/2K1R3 w - - 0 1",
+            "1k1r3q/1ppn3p/p4b2/4p3/8/P2N2P1/1PP1R1BP/2K1Q3 w - - 0 1",
+            "4r1k1/5ppp/8/3p4/3Pn3/2N1B3/5PPP/4R1K1 w - - 0 1",
+            "r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4",
+            "3qk3/8/8/3rp3/4K3/8/8/8 w - - 0 1",
+        ];
+        for fen in fens {
+            let board: Board = fen.parse().unwrap();
+            board.generate_moves(|mvs| {
+                for mv in mvs {
+                    if !board.colors(!board.side_to_move()).has(mv.to) {
+                        continue;
+                    }
+                    let base = exchange_eval(&board, mv, piece_value);
+                    for scale in [2, 3, 10] {
+                        let scaled = |p| match p {
+                            Piece::King => KING_VALUE,
+                            _ => piece_value(p) * scale,
+                        };
+                        let eval = exchange_eval(&board, mv, scaled);
+                        assert_eq!(eval.signum(), base.signum(), "{} in {}", mv, fen);
+                    }
+                }
+                false
+            });
+        }
+    }
+}
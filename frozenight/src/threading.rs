@@ -1,22 +1,59 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
 use std::ops::ControlFlow;
+use std::path::Path;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{channel, Receiver, Sender};
-use std::sync::{Arc, Mutex, RwLock};
-use std::time::Instant;
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
+#[cfg(feature = "thread-affinity")]
+use core_affinity::CoreId;
 use cozy_chess::{Board, Move};
 
-use crate::search::INVALID_MOVE;
+use crate::search::extract_line;
 use crate::time::{TimeConstraint, TimeManager};
 use crate::tt::TranspositionTable;
-use crate::{update_position, Eval, Frozenight, SearchInfo, SharedState, Statistics};
+use crate::{
+    root_exclusions, update_position, AspirationStats, Eval, Frozenight, SearchInfo, SharedState,
+    Statistics,
+};
 
 pub struct MtFrozenight {
     board: Board,
     prehistory: Vec<u64>,
     shared_state: Arc<RwLock<SharedState>>,
-    threads: Vec<(Arc<Statistics>, Sender<ThreadCommand>)>,
-    abort: Arc<AtomicBool>,
+    threads: Vec<(Arc<Statistics>, Sender<ThreadCommand>, JoinHandle<()>)>,
+    abort: AbortHandle,
+    current: Option<Arc<Mutex<MtSyncState>>>,
+    refutations: bool,
+    excluded: Vec<Move>,
+    trajectory: bool,
+    /// The cores search threads are pinned to, if affinity is enabled.
+    #[cfg(feature = "thread-affinity")]
+    affinity: Option<Vec<CoreId>>,
+}
+
+/// The longest refutation line reported for a root move.
+const REFUTATION_LENGTH: usize = 8;
+
+/// Estimated memory used by each search thread: the engine state it owns plus the default stack
+/// size of spawned threads.
+const THREAD_MEMORY: usize = std::mem::size_of::<Frozenight>() + 2 * 1024 * 1024;
+
+/// A cheaply cloneable handle that stops whichever search the [`MtFrozenight`] it was created
+/// from is currently running.
+#[derive(Clone, Default)]
+pub struct AbortHandle(Arc<AbortState>);
+
+#[derive(Default)]
+struct AbortState {
+    /// The abort flag of the current search. Stops are made while this is locked, so that a
+    /// waiting thread can't miss one between checking its flag and waiting.
+    current: Mutex<Arc<AtomicBool>>,
+    /// Notified whenever a flag is set, for threads waiting for their search to be stopped.
+    stopped: Condvar,
 }
 
 enum ThreadCommand {
@@ -25,30 +62,81 @@ enum ThreadCommand {
         max_nodes: u64,
         max_depth: i16,
         deadline: Option<Instant>,
+        wait_for_stop: bool,
+        refutations: bool,
+        excluded: Vec<Move>,
         state: Arc<Mutex<MtSyncState>>,
         abort: Arc<AtomicBool>,
+        handle: AbortHandle,
     },
     NewGame,
 }
 
+/// Receives progress reports from a search started by [`MtFrozenight::search_with_listener`].
+pub trait SearchListener: Send {
+    /// Called each time an iteration of the search completes.
+    fn on_info(&mut self, info: &SearchInfo);
+
+    /// Called exactly once when the search stops, with the most recent search info.
+    fn on_finish(&mut self, info: &SearchInfo);
+}
+
+impl<I, F> SearchListener for (I, F)
+where
+    I: FnMut(&SearchInfo) + Send,
+    F: FnMut(&SearchInfo) + Send,
+{
+    fn on_info(&mut self, info: &SearchInfo) {
+        (self.0)(info)
+    }
+
+    fn on_finish(&mut self, info: &SearchInfo) {
+        (self.1)(info)
+    }
+}
+
+/// The result of one completed iteration of a search, as recorded when
+/// [`MtFrozenight::set_trajectory`] is enabled.
+#[derive(Clone, Copy, Debug)]
+pub struct TrajectoryPoint {
+    pub depth: i16,
+    pub eval: Eval,
+    pub best_move: Move,
+    /// The nodes searched by all threads so far.
+    pub nodes: u64,
+    /// The time since the search started.
+    pub elapsed: Duration,
+}
+
 struct MtSyncState {
     recent_info: SearchInfo,
+    root_scores: Vec<(Move, Eval)>,
     tm: TimeManager,
-    info: Box<dyn FnMut(&SearchInfo) + Send>,
-    finish: Option<Box<dyn FnOnce(&SearchInfo) + Send>>,
+    listener: Box<dyn SearchListener>,
+    finished: bool,
     stats: Vec<Arc<Statistics>>,
+    start: Instant,
+    trajectory: Option<Vec<TrajectoryPoint>>,
 }
 
 impl MtFrozenight {
     pub fn new(hash_mb: usize) -> Self {
+        let board = Board::default();
         let mut this = MtFrozenight {
-            board: Default::default(),
-            prehistory: vec![],
+            prehistory: vec![board.hash()],
+            board,
             shared_state: Arc::new(RwLock::new(SharedState {
                 tt: TranspositionTable::new(hash_mb),
+                history: Default::default(),
             })),
             threads: vec![],
             abort: Default::default(),
+            current: None,
+            refutations: false,
+            excluded: vec![],
+            trajectory: false,
+            #[cfg(feature = "thread-affinity")]
+            affinity: None,
         };
         this.set_threads(1);
         this
@@ -59,17 +147,61 @@ impl MtFrozenight {
     }
 
     pub fn set_threads(&mut self, threads: usize) {
-        self.threads.resize_with(threads, || {
+        if threads < self.threads.len() {
+            self.abort();
+            join_threads(self.threads.drain(threads..));
+        }
+        while self.threads.len() < threads {
             let (sender, recv) = channel();
             let engine = Frozenight::create(self.shared_state.clone());
             let stats = engine.stats.clone();
-            std::thread::spawn(|| run_thread(engine, recv));
+            #[cfg(feature = "thread-affinity")]
+            let core = self
+                .affinity
+                .as_ref()
+                .map(|cores| cores[self.threads.len() % cores.len()]);
+            let handle = std::thread::spawn(move || {
+                #[cfg(feature = "thread-affinity")]
+                if let Some(core) = core {
+                    core_affinity::set_for_current(core);
+                }
+                run_thread(engine, recv)
+            });
             let _ = sender.send(ThreadCommand::SetPosition(
                 self.board.clone(),
                 self.prehistory.clone(),
             ));
-            (stats, sender)
-        });
+            self.threads.push((stats, sender, handle));
+        }
+    }
+
+    /// Pins each search thread to its own core, restarting the threads. Returns `false` if
+    /// affinity isn't supported on this platform.
+    #[cfg(feature = "thread-affinity")]
+    pub fn set_thread_affinity(&mut self, enabled: bool) -> bool {
+        let cores = match enabled {
+            true => match core_affinity::get_core_ids() {
+                Some(cores) if !cores.is_empty() => Some(cores),
+                _ => return false,
+            },
+            false => None,
+        };
+        let threads = self.threads.len();
+        self.set_threads(0);
+        self.affinity = cores;
+        self.set_threads(threads);
+        true
+    }
+
+    /// Thread affinity is not supported without the `thread-affinity` feature.
+    #[cfg(not(feature = "thread-affinity"))]
+    pub fn set_thread_affinity(&mut self, _enabled: bool) -> bool {
+        false
+    }
+
+    /// Stops the current search and waits for all of the search threads to exit.
+    pub fn shutdown(self) {
+        drop(self);
     }
 
     pub fn set_hash(&mut self, hash_mb: usize) {
@@ -81,14 +213,38 @@ impl MtFrozenight {
         state.tt = TranspositionTable::new(hash_mb);
     }
 
+    /// Empties the transposition table, for when searches must not be affected by earlier ones.
+    pub fn clear_hash(&mut self) {
+        self.abort();
+        self.shared_state.write().unwrap().tt.clear();
+    }
+
+    /// Writes the transposition table to the file at `path`, so that it can be restored with
+    /// [`load_tt`](Self::load_tt) to resume an analysis later.
+    pub fn save_tt(&mut self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        self.abort();
+        let mut out = BufWriter::new(File::create(path)?);
+        self.shared_state.read().unwrap().tt.save(&mut out)?;
+        out.flush()
+    }
+
+    /// Replaces the transposition table with one written by [`save_tt`](Self::save_tt). The
+    /// saved table must have the same size as the current one.
+    pub fn load_tt(&mut self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        self.abort();
+        let mut input = BufReader::new(File::open(path)?);
+        self.shared_state.write().unwrap().tt.load(&mut input)
+    }
+
     pub fn set_position(&mut self, position: Board, moves: impl Iterator<Item = Move>) {
         self.abort();
         let mut new = position;
         let age_inc = update_position(&mut new, &mut self.prehistory, &self.board, moves);
         self.board = new;
+        self.excluded.clear();
         self.shared_state.write().unwrap().tt.increment_age(age_inc);
 
-        for (_, thread) in &self.threads {
+        for (_, thread, _) in &self.threads {
             let _ = thread.send(ThreadCommand::SetPosition(
                 self.board.clone(),
                 self.prehistory.clone(),
@@ -96,61 +252,199 @@ impl MtFrozenight {
         }
     }
 
+    /// Prevents the search from playing any of `moves` from the current position. See
+    /// [`Frozenight::set_excluded_moves`].
+    pub fn set_excluded_moves(&mut self, moves: Vec<Move>) {
+        self.excluded = moves;
+    }
+
+    /// Forgets the history of previous searches and returns to the start position.
     pub fn new_game(&mut self) {
-        for (_, thread) in &self.threads {
+        self.set_position(Board::default(), std::iter::empty());
+        self.shared_state.read().unwrap().history.clear();
+        for (_, thread, _) in &self.threads {
             let _ = thread.send(ThreadCommand::NewGame);
         }
     }
 
+    /// Stops the current search, if there is one.
     pub fn abort(&mut self) {
-        self.abort.store(true, Ordering::Relaxed);
+        self.abort.stop();
+    }
+
+    /// Estimates the memory used by the engine in bytes, including the transposition table.
+    pub fn memory_usage(&self) -> usize {
+        self.shared_state.read().unwrap().tt.size_bytes() + self.thread_memory_usage()
+    }
+
+    /// Estimates the memory used by the search threads in bytes, excluding the transposition
+    /// table they share.
+    pub fn thread_memory_usage(&self) -> usize {
+        self.threads.len() * THREAD_MEMORY
+    }
+
+    /// Sets whether the final report of each search includes
+    /// [`refutations`](SearchInfo::refutations) of the inferior root moves.
+    pub fn set_refutations(&mut self, enabled: bool) {
+        self.refutations = enabled;
+    }
+
+    /// Enables recording each completed iteration of later searches, for tuning time management
+    /// without parsing the reported info. See [`MtFrozenight::last_search_trajectory`].
+    pub fn set_trajectory(&mut self, enabled: bool) {
+        self.trajectory = enabled;
+    }
+
+    /// The iterations completed by the current or most recent search, in order, or nothing if
+    /// recording wasn't enabled by [`MtFrozenight::set_trajectory`] when it started.
+    pub fn last_search_trajectory(&self) -> Vec<TrajectoryPoint> {
+        self.current
+            .as_ref()
+            .and_then(|state| state.lock().unwrap().trajectory.clone())
+            .unwrap_or_default()
     }
 
+    pub fn abort_handle(&self) -> AbortHandle {
+        self.abort.clone()
+    }
+
+    /// Returns the info from the most recently completed iteration of the current search, or
+    /// `None` if no iteration has completed yet.
+    pub fn current_info(&self) -> Option<SearchInfo> {
+        let state = self.current.as_ref()?.lock().unwrap();
+        match state.recent_info.depth {
+            0 => None,
+            _ => Some(state.recent_info.clone()),
+        }
+    }
+
+    /// Returns the score of each root move from the most recently completed iteration of the
+    /// current search, best first. Only the best move's score is exact; the rest are upper bounds.
+    pub fn root_move_scores(&self) -> Vec<(Move, Eval)> {
+        match &self.current {
+            Some(state) => state.lock().unwrap().root_scores.clone(),
+            None => vec![],
+        }
+    }
+
+    /// Starts searching the current position in the background, calling `info` each time an
+    /// iteration completes and `finish` once the search stops.
     pub fn search(
         &mut self,
         time: TimeConstraint,
         info: impl FnMut(&SearchInfo) + Send + 'static,
         finish: impl FnMut(&SearchInfo) + Send + 'static,
     ) {
-        self.abort();
-        self.abort = Default::default();
+        self.search_with_listener(time, (info, finish));
+    }
+
+    pub fn search_with_listener(
+        &mut self,
+        time: TimeConstraint,
+        listener: impl SearchListener + 'static,
+    ) {
+        let abort = Arc::new(AtomicBool::new(false));
+        self.abort.replace(abort.clone());
 
         let stats = self
             .threads
             .iter()
-            .map(|(stats, _)| stats.clone())
+            .map(|(stats, _, _)| stats.clone())
             .collect();
         let tm = TimeManager::new(&self.board, time);
         let mut deadline = tm.deadline();
 
+        // guess a move up front so that there is always a legal move to report, however early
+        // the search is stopped
+        let excluded = root_exclusions(&self.board, &self.excluded).to_vec();
+        let mut recent_info = SearchInfo::unsearched(&self.board);
+        let hash_move = self.shared_state.read().unwrap().tt.get_move(&self.board);
+        let hash_move = hash_move.filter(|mv| !excluded.contains(mv));
+        if let Some(mv) = hash_move.or_else(|| first_move(&self.board, &excluded)) {
+            recent_info.best_move = mv;
+        }
+
         let state = Arc::new(Mutex::new(MtSyncState {
-            recent_info: SearchInfo {
-                eval: Eval::DRAW,
-                nodes: 0,
-                depth: 0,
-                selective_depth: 0,
-                hashfull: 0,
-                best_move: INVALID_MOVE,
-                pv: vec![],
-            },
+            recent_info,
+            root_scores: vec![],
             tm,
-            info: Box::new(info),
-            finish: Some(Box::new(finish)),
+            listener: Box::new(listener),
+            finished: false,
             stats,
+            start: Instant::now(),
+            trajectory: self.trajectory.then(Vec::new),
         }));
+        self.current = Some(state.clone());
 
-        for (_, sender) in &self.threads {
+        for (_, sender, _) in &self.threads {
             let _ = sender.send(ThreadCommand::Go {
                 max_nodes: time.nodes,
                 max_depth: time.depth,
                 deadline: deadline.take(),
+                wait_for_stop: time.wait_for_stop,
+                refutations: self.refutations,
+                excluded: excluded.clone(),
                 state: state.clone(),
-                abort: self.abort.clone(),
+                abort: abort.clone(),
+                handle: self.abort.clone(),
             });
         }
     }
 }
 
+impl Drop for MtFrozenight {
+    fn drop(&mut self) {
+        self.abort();
+        join_threads(self.threads.drain(..));
+    }
+}
+
+/// Closes the command channels of `threads`, which makes them exit once they finish the command
+/// in progress, and waits for them to do so.
+fn join_threads(
+    threads: impl Iterator<Item = (Arc<Statistics>, Sender<ThreadCommand>, JoinHandle<()>)>,
+) {
+    let handles: Vec<_> = threads.map(|(_, _, handle)| handle).collect();
+    for handle in handles {
+        // a thread that panicked has already reported it, so there is nothing more to do
+        let _ = handle.join();
+    }
+}
+
+impl AbortHandle {
+    pub fn stop(&self) {
+        let current = self.0.current.lock().unwrap();
+        current.store(true, Ordering::Relaxed);
+        self.0.stopped.notify_all();
+    }
+
+    /// Installs the flag of a new search and stops the previous one under the same lock, so that
+    /// a concurrent stop always reaches one of them.
+    fn replace(&self, abort: Arc<AtomicBool>) {
+        let mut current = self.0.current.lock().unwrap();
+        current.store(true, Ordering::Relaxed);
+        *current = abort;
+        self.0.stopped.notify_all();
+    }
+
+    /// Blocks until `abort` is set.
+    fn wait(&self, abort: &AtomicBool) {
+        let mut current = self.0.current.lock().unwrap();
+        while !abort.load(Ordering::Relaxed) {
+            current = self.0.stopped.wait(current).unwrap();
+        }
+    }
+}
+
+fn first_move(board: &Board, excluded: &[Move]) -> Option<Move> {
+    let mut first = None;
+    board.generate_moves(|mvs| {
+        first = mvs.into_iter().find(|mv| !excluded.contains(mv));
+        first.is_some()
+    });
+    first
+}
+
 fn run_thread(mut engine: Frozenight, recv: Receiver<ThreadCommand>) {
     while let Ok(cmd) = recv.recv() {
         match cmd {
@@ -158,17 +452,24 @@ fn run_thread(mut engine: Frozenight, recv: Receiver<ThreadCommand>) {
                 engine.board = root;
                 engine.prehistory = prehistory;
             }
+            // the position is reset by the `SetPosition` sent just before this
             ThreadCommand::NewGame => {
                 engine.stats.clear();
+                engine.state.clear();
             }
             ThreadCommand::Go {
                 max_nodes,
                 max_depth,
                 deadline,
+                wait_for_stop,
+                refutations,
+                excluded,
                 state,
                 abort,
+                handle,
             } => {
-                engine.search_internal(
+                engine.excluded = excluded;
+                let reason = engine.search_internal(
                     max_depth,
                     max_nodes,
                     &abort,
@@ -180,35 +481,280 @@ fn run_thread(mut engine: Frozenight, recv: Receiver<ThreadCommand>) {
                             return ControlFlow::Continue(());
                         }
 
-                        let mut nodes = 0;
+                        let mut thread_nodes = vec![];
                         let mut selective_depth = 0;
+                        let mut effective_depth = 0;
+                        let mut aspiration = AspirationStats::default();
 
                         for stats in &state.stats {
-                            nodes += stats.nodes.load(Ordering::Relaxed);
+                            thread_nodes.push(stats.nodes.load(Ordering::Relaxed));
                             selective_depth =
                                 selective_depth.max(stats.selective_depth.load(Ordering::Relaxed));
+                            effective_depth =
+                                effective_depth.max(stats.effective_depth.load(Ordering::Relaxed));
+                            let asp = stats.aspiration();
+                            aspiration.fail_highs += asp.fail_highs;
+                            aspiration.fail_lows += asp.fail_lows;
+                            aspiration.researches += asp.researches;
                         }
 
                         state.recent_info = SearchInfo {
                             eval,
                             depth,
                             selective_depth,
-                            nodes,
+                            effective_depth,
+                            nodes: thread_nodes.iter().sum(),
+                            thread_nodes,
                             hashfull: searcher.shared.tt.hashfull(),
                             best_move: mv,
                             pv: searcher.extract_pv(depth),
+                            aspiration,
+                            stop_reason: None,
+                            refutations: vec![],
+                            claimable_draw: searcher.claimable_draw(mv),
                         };
-                        (state.info)(&state.recent_info);
+                        if let Some(trajectory) = &mut state.trajectory {
+                            trajectory.push(TrajectoryPoint {
+                                depth,
+                                eval,
+                                best_move: mv,
+                                nodes: state.recent_info.nodes,
+                                elapsed: state.start.elapsed(),
+                            });
+                        }
+                        state.root_scores = searcher.root_scores.clone();
+                        // stable, so the best move stays ahead of moves bounded by the same score
+                        state
+                            .root_scores
+                            .sort_by_key(|&(_, eval)| std::cmp::Reverse(eval));
+                        state.listener.on_info(&state.recent_info);
                         state.tm.update(&state.recent_info)
                     },
                 );
 
+                if wait_for_stop {
+                    handle.wait(&abort);
+                }
+
                 abort.store(true, Ordering::Relaxed);
+                // searching threads lock the shared state before the sync state, so do the same
+                let shared = engine.shared_state.read().unwrap();
                 let mut state = state.lock().unwrap();
-                if let Some(finish) = state.finish.take() {
-                    finish(&state.recent_info);
+                let state = &mut *state;
+                if !state.finished {
+                    state.finished = true;
+                    state.recent_info.stop_reason = Some(reason);
+                    if refutations {
+                        let best_move = state.recent_info.best_move;
+                        state.recent_info.refutations = state
+                            .root_scores
+                            .iter()
+                            .filter(|&&(mv, _)| mv != best_move)
+                            .map(|&(mv, _)| {
+                                let mut board = engine.board.clone();
+                                board.play_unchecked(mv);
+                                (mv, extract_line(&shared.tt, board, REFUTATION_LENGTH))
+                            })
+                            .filter(|(_, line)| !line.is_empty())
+                            .collect();
+                    }
+                    // every search thread is counted once; the thread that starts the search
+                    // doesn't search itself
+                    state.recent_info.thread_nodes = state
+                        .stats
+                        .iter()
+                        .map(|stats| stats.nodes.load(Ordering::Relaxed))
+                        .collect();
+                    state.recent_info.nodes = state.recent_info.thread_nodes.iter().sum();
+                    state.listener.on_finish(&state.recent_info);
                 }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::{channel, RecvTimeoutError, TryRecvError};
+
+    use super::*;
+    use crate::StopReason;
+
+    #[test]
+    fn waiting_search_finishes_only_when_stopped() {
+        let mut engine = MtFrozenight::new(1);
+        engine.set_threads(2);
+        let (send, recv) = channel();
+        let time = TimeConstraint {
+            depth: 1,
+            wait_for_stop: true,
+            ..TimeConstraint::INFINITE
+        };
+        engine.search(time, |_| {}, move |info| send.send(info.depth).unwrap());
+
+        // the depth limit is reached almost at once, but the search waits for the stop
+        let wait = recv.recv_timeout(Duration::from_millis(100));
+        assert_eq!(wait, Err(RecvTimeoutError::Timeout));
+        engine.abort_handle().stop();
+        assert_eq!(recv.recv().unwrap(), 1);
+    }
+
+    /// Runs a search to completion and returns its final report.
+    fn search(engine: &mut MtFrozenight, time: TimeConstraint) -> SearchInfo {
+        let (send, recv) = channel();
+        engine.search(time, |_| {}, move |info| send.send(info.clone()).unwrap());
+        recv.recv().unwrap()
+    }
+
+    #[test]
+    fn thread_nodes_add_up() {
+        let mut engine = MtFrozenight::new(1);
+        engine.set_threads(2);
+        let info = search(&mut engine, TimeConstraint::fixed_depth(6));
+        assert_eq!(info.thread_nodes.len(), 2);
+        assert_eq!(info.thread_nodes.iter().sum::<u64>(), info.nodes);
+    }
+
+    #[test]
+    fn pinned_threads_search() {
+        let mut engine = MtFrozenight::new(1);
+        engine.set_threads(2);
+        engine.set_thread_affinity(true);
+        let info = search(&mut engine, TimeConstraint::fixed_depth(4));
+        assert_eq!(info.thread_nodes.len(), 2);
+        assert!(Board::default().is_legal(info.best_move));
+    }
+
+    #[test]
+    fn shutdown_finishes_the_search_first() {
+        let mut engine = MtFrozenight::new(1);
+        engine.set_threads(4);
+        let (send, recv) = channel();
+        let time = TimeConstraint {
+            wait_for_stop: true,
+            ..TimeConstraint::INFINITE
+        };
+        engine.search(
+            time,
+            |_| {},
+            move |info| send.send(info.stop_reason).unwrap(),
+        );
+        std::thread::sleep(Duration::from_millis(50));
+
+        engine.shutdown();
+        assert_eq!(recv.try_recv().unwrap(), Some(StopReason::Abort));
+        // nothing that could still report a result is left running
+        assert_eq!(recv.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn saved_tables_must_match_in_size() {
+        let path = std::env::temp_dir().join("frozenight-save-tt-test");
+        let mut engine = MtFrozenight::new(1);
+        engine.save_tt(&path).unwrap();
+        engine.load_tt(&path).unwrap();
+        engine.set_hash(2);
+        assert!(engine.load_tt(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn new_game_returns_to_the_start_position() {
+        let mut engine = MtFrozenight::new(1);
+        engine.set_threads(2);
+        let fen = "4k3/8/8/8/8/8/8/R3K3 w Q - 0 1";
+        engine.set_position(fen.parse().unwrap(), std::iter::empty());
+        engine.new_game();
+        assert!(engine.board().same_position(&Board::default()));
+
+        let info = search(&mut engine, TimeConstraint::fixed_depth(4));
+        let mut board = Board::default();
+        for mv in info.pv {
+            assert!(board.is_legal(mv));
+            board.play_unchecked(mv);
+        }
+    }
+
+    #[test]
+    fn stops_only_affect_the_running_search() {
+        let mut engine = MtFrozenight::new(1);
+        let (send, recv) = channel();
+        for _ in 0..20 {
+            // a stop before the search starts is ignored
+            engine.abort();
+            let finish = send.clone();
+            engine.search(
+                TimeConstraint::fixed_depth(3),
+                |_| {},
+                move |info| finish.send(info.stop_reason).unwrap(),
+            );
+            assert_eq!(recv.recv().unwrap(), Some(StopReason::Depth));
+
+            // a stop right after the search starts still produces a legal move
+            let finish = send.clone();
+            engine.search(
+                TimeConstraint::INFINITE,
+                |_| {},
+                move |info| finish.send(info.best_move).unwrap(),
+            );
+            engine.abort();
+            assert!(engine.board().is_legal(recv.recv().unwrap()));
+        }
+    }
+
+    #[test]
+    fn short_searches_report_legal_moves() {
+        let mut engine = MtFrozenight::new(1);
+        for _ in 0..10 {
+            let info = search(
+                &mut engine,
+                TimeConstraint::movetime(Duration::from_millis(1)),
+            );
+            assert!(engine.board().is_legal(info.best_move));
+        }
+    }
+
+    #[test]
+    fn refutations_are_legal_lines() {
+        let mut engine = MtFrozenight::new(1);
+        engine.set_refutations(true);
+        let info = search(&mut engine, TimeConstraint::fixed_depth(4));
+
+        assert!(!info.refutations.is_empty());
+        for (mv, line) in info.refutations {
+            assert_ne!(mv, info.best_move);
+            assert!((1..=REFUTATION_LENGTH).contains(&line.len()));
+            let mut board = engine.board().clone();
+            board.play(mv);
+            for mv in line {
+                assert!(board.is_legal(mv));
+                board.play_unchecked(mv);
+            }
+        }
+    }
+
+    #[test]
+    fn trajectory_is_recorded_in_order() {
+        let mut engine = MtFrozenight::new(1);
+        engine.set_threads(2);
+        engine.set_trajectory(true);
+        let info = search(&mut engine, TimeConstraint::fixed_depth(6));
+
+        let trajectory = engine.last_search_trajectory();
+        assert_eq!(trajectory.last().unwrap().depth, info.depth);
+        for pair in trajectory.windows(2) {
+            assert!(pair[0].depth < pair[1].depth);
+            assert!(pair[0].nodes <= pair[1].nodes);
+            assert!(pair[0].elapsed <= pair[1].elapsed);
+        }
+    }
+
+    #[test]
+    fn best_move_scores_first() {
+        let mut engine = MtFrozenight::new(1);
+        let info = search(&mut engine, TimeConstraint::fixed_depth(4));
+        let scores = engine.root_move_scores();
+        assert_eq!(scores.len(), 20);
+        assert_eq!(scores[0], (info.best_move, info.eval));
+    }
+}
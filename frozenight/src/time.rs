@@ -5,15 +5,21 @@ use cozy_chess::Board;
 
 use crate::SearchInfo;
 
+/// Limits on how long a search may run.
 #[derive(Copy, Clone, Debug)]
 pub struct TimeConstraint {
+    /// A soft limit on the nodes searched by each thread, checked after each iteration.
     pub nodes: u64,
     pub depth: i16,
     pub clock: Option<Duration>,
+    /// The increment per move. With neither an increment nor `moves_to_go`, this is sudden death.
     pub increment: Duration,
     pub overhead: Duration,
+    /// The moves left until the clock is next topped up, including this one. See [`MovesToGo`].
     pub moves_to_go: Option<u32>,
     pub use_all_time: bool,
+    /// Corresponds to `go infinite`: the search does not finish until it is aborted.
+    pub wait_for_stop: bool,
 }
 
 impl TimeConstraint {
@@ -25,9 +31,105 @@ impl TimeConstraint {
         overhead: Duration::ZERO,
         moves_to_go: None,
         use_all_time: true,
+        wait_for_stop: false,
     };
+
+    /// Corresponds to `go wtime/btime/winc/binc` for the side to move.
+    pub fn clock(remaining: Duration, increment: Duration) -> Self {
+        TimeConstraint {
+            clock: Some(remaining),
+            increment,
+            use_all_time: false,
+            ..TimeConstraint::INFINITE
+        }
+    }
+
+    /// Corresponds to `go movetime`.
+    pub fn movetime(time: Duration) -> Self {
+        TimeConstraint {
+            clock: Some(time),
+            ..TimeConstraint::INFINITE
+        }
+    }
+
+    /// Corresponds to `go depth`.
+    pub fn fixed_depth(depth: i16) -> Self {
+        TimeConstraint {
+            depth,
+            ..TimeConstraint::INFINITE
+        }
+    }
+
+    /// Corresponds to `go nodes`.
+    pub fn fixed_nodes(nodes: u64) -> Self {
+        TimeConstraint {
+            nodes,
+            ..TimeConstraint::INFINITE
+        }
+    }
+
+    /// The time a search is expected to take and the time after which it is cut off, with the
+    /// move overhead already subtracted. Returns `None` if the search is not limited by time.
+    pub fn budget(&self) -> Option<(Duration, Duration)> {
+        let clock = self.clock?;
+
+        let soft = match () {
+            _ if self.use_all_time => clock,
+            _ if self.increment.is_zero() && self.moves_to_go.is_none() => {
+                // sudden death: keep a reserve, and spend a smaller share of what is
+                // left as the clock runs down since it can never be replenished
+                let share = match clock.as_secs() {
+                    0..=9 => 60,
+                    10..=59 => 50,
+                    _ => 40,
+                };
+                (clock - clock / 10) / share
+            }
+            _ => {
+                let mtg = self.moves_to_go.unwrap_or(45) + 5;
+                clock.saturating_sub(self.increment) / mtg + self.increment / 2
+            }
+        };
+        let hard = clock / 2;
+
+        Some((
+            soft.saturating_sub(self.overhead).max(MIN_TIME),
+            hard.saturating_sub(self.overhead).max(MIN_TIME),
+        ))
+    }
 }
 
+/// Counts down the moves left in a repeating time control, for playing games through the library.
+#[derive(Clone, Copy, Debug)]
+pub struct MovesToGo {
+    moves: u32,
+    left: u32,
+}
+
+impl MovesToGo {
+    /// Starts a control of `moves` moves, which begins again after its last move.
+    pub fn new(moves: u32) -> Self {
+        assert!(moves > 0, "a time control must have at least one move");
+        MovesToGo { moves, left: moves }
+    }
+
+    /// The moves left in the current control, for [`TimeConstraint::moves_to_go`].
+    pub fn get(&self) -> u32 {
+        self.left
+    }
+
+    /// Counts a move played by the engine, starting over after the last move of the control.
+    pub fn played(&mut self) {
+        self.left = match self.left {
+            1 => self.moves,
+            left => left - 1,
+        };
+    }
+}
+
+/// The least amount of time a search is given, no matter how large the move overhead is.
+const MIN_TIME: Duration = Duration::from_millis(1);
+
 pub(crate) struct TimeManager {
     soft_deadline: Option<Instant>,
     hard_deadline: Option<Instant>,
@@ -37,27 +139,11 @@ pub(crate) struct TimeManager {
 impl TimeManager {
     pub fn new(board: &Board, time: TimeConstraint) -> Self {
         let now = Instant::now();
+        let budget = time.budget();
         TimeManager {
             one_reply: !time.use_all_time && time.clock.is_some() && one_reply(board),
-            hard_deadline: time
-                .clock
-                .map(|clock| now + (clock / 2).saturating_sub(time.overhead)),
-            soft_deadline: time
-                .clock
-                .map(|clock| {
-                    if time.use_all_time {
-                        return clock;
-                    }
-
-                    let mtg = time.moves_to_go.unwrap_or(45) + 5;
-
-                    clock.saturating_sub(time.increment) / mtg + time.increment / 2
-                })
-                .map(|amt| {
-                    now + amt
-                        .saturating_sub(time.overhead)
-                        .max(Duration::from_millis(1))
-                }),
+            hard_deadline: budget.map(|(_, hard)| now + hard),
+            soft_deadline: budget.map(|(soft, _)| now + soft),
         }
     }
 
@@ -88,3 +174,70 @@ fn one_reply(board: &Board) -> bool {
     });
     moves == 1
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overhead_leaves_at_least_a_millisecond() {
+        let time = TimeConstraint {
+            clock: Some(Duration::from_millis(10)),
+            overhead: Duration::from_millis(50),
+            ..TimeConstraint::INFINITE
+        };
+        assert_eq!(time.budget(), Some((MIN_TIME, MIN_TIME)));
+    }
+
+    #[test]
+    fn sudden_death_keeps_a_reserve() {
+        let overhead = Duration::from_millis(10);
+        for secs in [60, 10, 1] {
+            let clock = Duration::from_secs(secs);
+            let time = TimeConstraint {
+                clock: Some(clock),
+                overhead,
+                use_all_time: false,
+                ..TimeConstraint::INFINITE
+            };
+            let (soft, hard) = time.budget().unwrap();
+            assert!(soft <= hard && hard + overhead < clock);
+            // with no time coming back, each move only gets a small share of the clock
+            assert!(soft + overhead <= clock / 40);
+        }
+    }
+
+    #[test]
+    fn clock_leaves_time_for_later_moves() {
+        let clock = Duration::from_secs(1);
+        let (soft, hard) = TimeConstraint::clock(clock, Duration::ZERO)
+            .budget()
+            .unwrap();
+        assert!(soft <= hard && hard < clock);
+    }
+
+    #[test]
+    fn moves_to_go_spreads_each_control() {
+        let control = Duration::from_secs(5 * 60);
+        let mut moves_to_go = MovesToGo::new(40);
+        let mut clock = control;
+        for _ in 0..2 {
+            assert_eq!(moves_to_go.get(), 40);
+            for _ in 0..40 {
+                let time = TimeConstraint {
+                    clock: Some(clock),
+                    moves_to_go: Some(moves_to_go.get()),
+                    use_all_time: false,
+                    ..TimeConstraint::INFINITE
+                };
+                let (soft, hard) = time.budget().unwrap();
+                assert!(soft <= hard && hard < clock);
+                clock -= soft;
+                moves_to_go.played();
+            }
+            // most of the time was used, but some was kept in reserve until the next control
+            assert!(clock > Duration::ZERO && clock < control / 5);
+            clock += control;
+        }
+    }
+}
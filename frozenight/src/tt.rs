@@ -1,3 +1,4 @@
+use std::io::{Error, ErrorKind, Read, Write};
 use std::sync::atomic::{AtomicU64, Ordering};
 
 use bytemuck::{Pod, Zeroable};
@@ -13,9 +14,12 @@ pub struct TranspositionTable {
 
 const ENTRIES_PER_MB: usize = 1024 * 1024 / std::mem::size_of::<TtEntry>();
 
+const FILE_MAGIC: [u8; 8] = *b"FZNTT001";
+
 impl TranspositionTable {
     pub fn new(hash_mb: usize) -> Self {
-        assert!(hash_mb > 0);
+        // some GUIs ignore the advertised minimum, so treat 0 as the smallest table
+        let hash_mb = hash_mb.max(1);
         TranspositionTable {
             entries: (0..hash_mb * ENTRIES_PER_MB)
                 .map(|_| TtEntry::default())
@@ -34,6 +38,17 @@ impl TranspositionTable {
         }
     }
 
+    /// Empties the table, as if it had just been created.
+    pub fn clear(&mut self) {
+        for entry in &mut *self.entries {
+            *entry = TtEntry::default();
+        }
+    }
+
+    pub fn size_bytes(&self) -> usize {
+        self.entries.len() * std::mem::size_of::<TtEntry>()
+    }
+
     pub fn hashfull(&self) -> usize {
         self.entries
             .iter()
@@ -135,11 +150,78 @@ impl TranspositionTable {
             .store(position.board.hash() ^ data, Ordering::Relaxed);
     }
 
+    /// Writes the table to `out`, after a header recording the entry size and count.
+    pub fn save(&self, out: &mut impl Write) -> std::io::Result<()> {
+        out.write_all(&FILE_MAGIC)?;
+        out.write_all(&(std::mem::size_of::<TtEntry>() as u64).to_le_bytes())?;
+        out.write_all(&(self.entries.len() as u64).to_le_bytes())?;
+        out.write_all(&[self.search_number])?;
+        for entry in &*self.entries {
+            out.write_all(&entry.hash.load(Ordering::Relaxed).to_le_bytes())?;
+            out.write_all(&entry.data.load(Ordering::Relaxed).to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the contents of the table with a table written by [`save`](Self::save). Tables
+    /// of a different size are rejected, leaving this table unchanged. If reading fails partway
+    /// through, the table is cleared instead.
+    pub fn load(&mut self, input: &mut impl Read) -> std::io::Result<()> {
+        let mut magic = [0; 8];
+        input.read_exact(&mut magic)?;
+        if magic != FILE_MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "not a saved hash table"));
+        }
+        let entry_size = read_u64(input)?;
+        let entries = read_u64(input)?;
+        if entry_size != std::mem::size_of::<TtEntry>() as u64 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "saved hash table has an incompatible entry format",
+            ));
+        }
+        if entries != self.entries.len() as u64 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "saved hash table has {} entries, but the current one has {}",
+                    entries,
+                    self.entries.len()
+                ),
+            ));
+        }
+        let mut search_number = [0];
+        input.read_exact(&mut search_number)?;
+
+        // read directly into the existing table to avoid needing memory for two of them
+        let result = read_entries(&mut self.entries, input);
+        if let Err(e) = result {
+            self.clear();
+            return Err(e);
+        }
+        self.search_number = search_number[0];
+        Ok(())
+    }
+
     pub fn increment_age(&mut self, by: u8) {
         self.search_number = self.search_number.wrapping_add(by);
     }
 }
 
+fn read_entries(entries: &mut [TtEntry], input: &mut impl Read) -> std::io::Result<()> {
+    for entry in entries {
+        *entry.hash.get_mut() = read_u64(input)?;
+        *entry.data.get_mut() = read_u64(input)?;
+    }
+    Ok(())
+}
+
+fn read_u64(input: &mut impl Read) -> std::io::Result<u64> {
+    let mut bytes = [0; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
 #[derive(Copy, Clone, Debug)]
 pub struct TableEntry {
     pub mv: Move,
@@ -0,0 +1,38 @@
+use cozy_chess::{Board, File, Move, Piece, Square};
+
+/// Parses a move in UCI long algebraic notation, returning `None` if it is malformed or illegal
+/// in `board`. Unless `chess960` is set, castling is given as the king moving two squares.
+pub fn parse_move(board: &Board, mv: &str, chess960: bool) -> Option<Move> {
+    let mut mv: Move = mv.parse().ok()?;
+    if !chess960 && mv.from.file() == File::E && board.piece_on(mv.from) == Some(Piece::King) {
+        if mv.to.file() == File::G {
+            mv.to = Square::new(File::H, mv.to.rank());
+        } else if mv.to.file() == File::C {
+            mv.to = Square::new(File::A, mv.to.rank());
+        }
+    }
+    board.is_legal(mv).then(|| mv)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_castling_and_promotions() {
+        let board: Board = "r3k2r/1P6/8/8/8/8/8/R3K2R w KQkq - 0 1".parse().unwrap();
+        let parse = |mv: &str, chess960| parse_move(&board, mv, chess960).map(|m| m.to_string());
+
+        assert_eq!(parse("e1g1", false).as_deref(), Some("e1h1"));
+        assert_eq!(parse("e1c1", false).as_deref(), Some("e1a1"));
+        assert_eq!(parse("e1h1", true).as_deref(), Some("e1h1"));
+        assert_eq!(parse("e1g1", true), None);
+        assert_eq!(parse("b7a8q", false).as_deref(), Some("b7a8q"));
+        assert_eq!(parse("b7b8n", false).as_deref(), Some("b7b8n"));
+        assert_eq!(parse("b7b8", false), None);
+        assert_eq!(parse("e1e3", false), None);
+        assert_eq!(parse("a8a7", false), None);
+        assert_eq!(parse("e9e8", false), None);
+        assert_eq!(parse("", false), None);
+    }
+}
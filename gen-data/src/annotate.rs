@@ -1,18 +1,15 @@
 use std::fs::File;
-use std::io::{stdout, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
 use std::ops::ControlFlow;
 use std::path::PathBuf;
-use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Mutex;
-use std::time::Instant;
 
 use bytemuck::Zeroable;
-use cozy_chess::Color;
-use frozenight::{Frozenight, TimeConstraint};
+use frozenight::{static_eval_board, white_relative, Frozenight, TimeConstraint};
 use marlinformat::PackedBoard;
 use structopt::StructOpt;
 
-use crate::{eta, CommonOptions};
+use crate::CommonOptions;
 
 #[derive(StructOpt)]
 pub(crate) struct Options {
@@ -22,22 +19,34 @@ pub(crate) struct Options {
 
     #[structopt(short = "n", long)]
     nodes: Option<u64>,
-    #[structopt(short = "d", long, required_unless("nodes"))]
+    #[structopt(short = "d", long, required_unless_one(&["nodes", "static_eval"]))]
     depth: Option<i16>,
+
+    /// Use the network's static eval instead of searching. The best move is unknown, so the flags
+    /// describing it are left clear
+    #[structopt(long = "static", conflicts_with_all(&["nodes", "depth"]))]
+    static_eval: bool,
+
+    /// Leave positions that were annotated by a previous run untouched
+    #[structopt(long)]
+    skip_annotated: bool,
+
+    /// Write positions in the same order as the input instead of as they finish
+    #[structopt(long)]
+    preserve_order: bool,
 }
 
 impl Options {
     pub fn run(self, opt: CommonOptions) -> std::io::Result<()> {
-        let start = Instant::now();
-        let games = AtomicUsize::new(0);
-
         let mut input = File::open(self.input)?;
         let total_positions =
             input.seek(SeekFrom::End(0))? / std::mem::size_of::<PackedBoard>() as u64;
         input.seek(SeekFrom::Start(0))?;
-        let input = Mutex::new(BufReader::new(input));
+        let input = Mutex::new((BufReader::new(input), 0));
+        // returns the index of the first position read
         let next = |boards: &mut Vec<_>| {
             let mut data = input.lock().unwrap();
+            let (data, read) = &mut *data;
             boards.clear();
             for _ in 0..64 {
                 let mut board = PackedBoard::zeroed();
@@ -45,76 +54,89 @@ impl Options {
                     boards.push(board);
                 };
             }
+            *read += boards.len() as u64;
+            *read - boards.len() as u64
         };
 
-        let output = Mutex::new(BufWriter::new(
-            File::options()
-                .create_new(true)
-                .write(true)
-                .open(self.output)?,
-        ));
+        let record = std::mem::size_of::<PackedBoard>() as u64;
+        let output = File::options()
+            .create_new(true)
+            .write(true)
+            .open(self.output)?;
+        if self.preserve_order {
+            // chunks finish out of order, so each is written to its place in a full-size file
+            output.set_len(total_positions * record)?;
+        }
+        let output = Mutex::new(BufWriter::new(output));
 
         opt.parallel(
+            total_positions as usize,
+            "positions",
             || (Vec::with_capacity(64), Frozenight::new(64)),
             |(boards, engine)| {
-                next(boards);
+                let index = next(boards);
                 if boards.is_empty() {
                     return ControlFlow::Break(());
                 }
 
                 for packed in &mut *boards {
-                    let (board, _, wdl, _) = packed.unpack().unwrap();
-
-                    engine.new_game();
-                    engine.set_position(board.clone(), std::iter::empty());
-                    let info = engine.search(
-                        TimeConstraint {
-                            nodes: self.nodes.unwrap_or(u64::MAX),
-                            depth: self.depth.unwrap_or(250),
-                            ..TimeConstraint::INFINITE
-                        },
-                        |_| {},
-                    );
-
-                    let white_eval = match board.side_to_move() {
-                        Color::White => info.eval,
-                        Color::Black => -info.eval,
+                    let (board, _, wdl, extra) = packed.unpack().unwrap();
+
+                    if self.skip_annotated && extra & 1 << 3 != 0 {
+                        continue;
+                    }
+
+                    let (eval, capture, gives_check) = match self.static_eval {
+                        true => (static_eval_board(&board), false, false),
+                        false => {
+                            engine.new_game();
+                            engine.set_position(board.clone(), std::iter::empty());
+                            let info = engine.search(
+                                TimeConstraint {
+                                    nodes: self.nodes.unwrap_or(u64::MAX),
+                                    depth: self.depth.unwrap_or(250),
+                                    ..TimeConstraint::INFINITE
+                                },
+                                |_| {},
+                            );
+
+                            let mv = info.best_move;
+                            let capture = board.colors(!board.side_to_move()).has(mv.to);
+                            let gives_check = {
+                                let mut b = board.clone();
+                                b.play_unchecked(mv);
+                                !b.checkers().is_empty()
+                            };
+                            (info.eval, capture, gives_check)
+                        }
                     };
 
-                    let capture = board.colors(!board.side_to_move()).has(info.best_move.to);
+                    let white_eval = white_relative(eval, board.side_to_move());
                     let in_check = !board.checkers().is_empty();
-                    let gives_check = {
-                        let mut b = board.clone();
-                        b.play_unchecked(info.best_move);
-                        !b.checkers().is_empty()
-                    };
 
-                    let extra = capture as u8 | (in_check as u8) << 1 | (gives_check as u8) << 2;
+                    // bit 3 marks the position as annotated, since an eval of 0 is legitimate
+                    let extra =
+                        capture as u8 | (in_check as u8) << 1 | (gives_check as u8) << 2 | 1 << 3;
 
                     *packed = PackedBoard::pack(&board, white_eval.raw(), wdl, extra);
                 }
 
                 output
                     .lock()
-                    .map(|mut file| file.write_all(bytemuck::cast_slice(&boards)))
+                    .map(|mut file| {
+                        if self.preserve_order {
+                            file.seek(SeekFrom::Start(index * record))?;
+                        }
+                        file.write_all(bytemuck::cast_slice(&boards))
+                    })
                     .unwrap()
                     .unwrap();
 
-                let completed = games.fetch_add(boards.len(), Ordering::Relaxed) + boards.len();
-                let completion = completed as f64 / total_positions as f64;
-                print!(
-                    "\r\x1b[K{:>6.2}% complete. ETA: {}",
-                    completion * 100.0,
-                    eta(start.elapsed().as_secs_f64(), completion),
-                );
-                stdout().flush().unwrap();
-
-                ControlFlow::Continue(())
+                ControlFlow::Continue(boards.len())
             },
+            String::new,
         );
 
-        println!();
-
         Ok(())
     }
 }
@@ -0,0 +1,70 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::PathBuf;
+
+use bytemuck::Zeroable;
+use cozy_chess::Color;
+use frozenight::Eval;
+use marlinformat::PackedBoard;
+use structopt::StructOpt;
+
+use crate::CommonOptions;
+
+#[derive(StructOpt)]
+pub struct Options {
+    input: PathBuf,
+    output: PathBuf,
+}
+
+impl Options {
+    pub(super) fn run(self, _: CommonOptions) -> std::io::Result<()> {
+        let mut input = BufReader::new(File::open(self.input)?);
+        let mut output = BufWriter::new(
+            OpenOptions::new()
+                .create_new(true)
+                .write(true)
+                .open(self.output)?,
+        );
+
+        let mut board = PackedBoard::zeroed();
+        while input.read_exact(bytemuck::bytes_of_mut(&mut board)).is_ok() {
+            let (board, eval, wdl, extra) = board.unpack().unwrap();
+
+            // stored evals are white-relative, but EPD `ce` is relative to the side to move
+            let eval = match board.side_to_move() {
+                Color::White => eval,
+                Color::Black => -eval,
+            };
+            let result = match wdl {
+                0 => "0-1",
+                1 => "1/2-1/2",
+                _ => "1-0",
+            };
+
+            // EPD has only the first four FEN fields, with the move counters as opcodes
+            let fen = board.to_string();
+            let fields: Vec<_> = fen.split_ascii_whitespace().take(4).collect();
+            write!(
+                output,
+                "{} hmvc {}; fmvn {}; c9 \"{}\"; ce {};",
+                fields.join(" "),
+                board.halfmove_clock(),
+                board.fullmove_number(),
+                result,
+                eval / Eval::CP_SCALE
+            )?;
+            if extra & 1 << 0 != 0 {
+                write!(output, " capture;")?;
+            }
+            if extra & 1 << 1 != 0 {
+                write!(output, " incheck;")?;
+            }
+            if extra & 1 << 2 != 0 {
+                write!(output, " givescheck;")?;
+            }
+            writeln!(output)?;
+        }
+
+        output.flush()
+    }
+}
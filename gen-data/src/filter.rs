@@ -3,10 +3,13 @@ use std::{
     io::{BufReader, BufWriter, Read, Write},
     ops::ControlFlow,
     path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
     sync::Mutex,
 };
 
 use bytemuck::Zeroable;
+use cozy_chess::Color;
+use frozenight::Eval;
 use marlinformat::PackedBoard;
 use structopt::StructOpt;
 
@@ -28,11 +31,24 @@ pub struct Options {
     filter_in_check: bool,
     #[structopt(short = "g", long)]
     filter_give_check: bool,
+
+    /// Keep only positions with at least this many pieces, including kings and pawns
+    #[structopt(long)]
+    min_pieces: Option<u32>,
+    /// Keep only positions with at most this many pieces, including kings and pawns
+    #[structopt(long)]
+    max_pieces: Option<u32>,
+    /// Keep only positions with this side to move (white or black)
+    #[structopt(long, parse(try_from_str = parse_color))]
+    stm: Option<Color>,
 }
 
 impl Options {
     pub(super) fn run(self, opt: CommonOptions) -> std::io::Result<()> {
-        let input = Mutex::new(BufReader::new(File::open(self.input)?));
+        let input = File::open(self.input)?;
+        let total_positions = input.metadata()?.len() as usize / std::mem::size_of::<PackedBoard>();
+        let opt = opt.for_chunks(total_positions, 1024);
+        let input = Mutex::new(BufReader::new(input));
         let next = |boards: &mut Vec<_>| {
             let mut data = input.lock().unwrap();
             boards.clear();
@@ -52,16 +68,22 @@ impl Options {
                 ?,
         ));
 
+        let kept = AtomicUsize::new(0);
+
         opt.parallel(
+            total_positions,
+            "positions",
             || Vec::with_capacity(1024),
             |boards| {
                 next(boards);
                 if boards.is_empty() {
                     return ControlFlow::Break(());
                 }
+                let count = boards.len();
 
                 boards.retain(|board| {
-                    let (_board, eval, _wdl, extra) = board.unpack().unwrap();
+                    let (board, eval, _wdl, extra) = board.unpack().unwrap();
+                    let pieces = board.occupied().len();
 
                     if self.filter_capture && extra & 1 << 0 != 0 {
                         false
@@ -73,13 +95,20 @@ impl Options {
                         false
                     } else if matches!(
                         self.filter_eval,
-                        Some(cp_threshold) if eval.abs() >= cp_threshold * 5
+                        Some(cp_threshold) if eval.abs() >= cp_threshold * Eval::CP_SCALE
                     ) {
                         false
+                    } else if matches!(self.min_pieces, Some(min) if pieces < min) {
+                        false
+                    } else if matches!(self.max_pieces, Some(max) if pieces > max) {
+                        false
+                    } else if matches!(self.stm, Some(stm) if board.side_to_move() != stm) {
+                        false
                     } else {
                         true
                     }
                 });
+                kept.fetch_add(boards.len(), Ordering::Relaxed);
 
                 output
                     .lock()
@@ -87,10 +116,25 @@ impl Options {
                     .unwrap()
                     .unwrap();
 
-                ControlFlow::Continue(())
+                ControlFlow::Continue(count)
             },
+            String::new,
+        );
+
+        println!(
+            "Kept {} of {} positions",
+            kept.into_inner(),
+            total_positions
         );
 
         Ok(())
     }
 }
+
+fn parse_color(s: &str) -> Result<Color, String> {
+    match s {
+        "white" | "w" => Ok(Color::White),
+        "black" | "b" => Ok(Color::Black),
+        _ => Err(format!("invalid side to move: {s}")),
+    }
+}
@@ -1,20 +1,20 @@
 use std::collections::HashSet;
-use std::fs::OpenOptions;
-use std::io::{stdout, BufWriter, Write};
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Write};
 use std::ops::ControlFlow;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
-use std::time::Instant;
 
-use cozy_chess::{Board, Color, GameStatus, Piece};
+use bytemuck::Zeroable;
+use cozy_chess::{Board, Color, GameStatus, Move, Piece};
 use cozy_syzygy::{Tablebase, Wdl};
-use frozenight::{Frozenight, TimeConstraint};
+use frozenight::{Eval, Frozenight, TimeConstraint};
 use marlinformat::PackedBoard;
 use rand::prelude::*;
 use structopt::StructOpt;
 
-use crate::{eta, CommonOptions};
+use crate::CommonOptions;
 
 #[derive(StructOpt)]
 pub(crate) struct Options {
@@ -38,6 +38,28 @@ pub(crate) struct Options {
 
     #[structopt(short = "r", long, default_value = "0.0")]
     random_move: f64,
+    /// Only play random moves during the first this many plies of each game
+    #[structopt(long)]
+    random_plies: Option<usize>,
+
+    /// Append to an existing output file instead of refusing to overwrite it
+    #[structopt(long)]
+    resume: bool,
+
+    /// Drop positions that have already been generated during this run
+    #[structopt(long)]
+    dedup: bool,
+
+    /// Distribute games round-robin across this many output files
+    #[structopt(long, default_value = "1")]
+    shards: usize,
+
+    /// Adjudicate a game as won once the search eval is at least this many centipawns
+    #[structopt(long)]
+    win_adj_score: Option<i16>,
+    /// Number of consecutive plies the eval must favor the same side before adjudicating
+    #[structopt(long, default_value = "8")]
+    win_adj_moves: u32,
 }
 
 impl Options {
@@ -49,79 +71,116 @@ impl Options {
 
         let tb = opt.syzygy();
 
-        let output = OpenOptions::new()
-            .create_new(true)
-            .write(true)
-            .open(&self.output)?;
-        let output = Mutex::new(BufWriter::new(output));
+        let paths: Vec<PathBuf> = match self.shards {
+            0 | 1 => vec![self.output.clone()],
+            n => (0..n).map(|i| shard_path(&self.output, i)).collect(),
+        };
+
+        let mut existing = 0;
+        let mut outputs = Vec::with_capacity(paths.len());
+        let mut seen = HashSet::new();
+        for path in &paths {
+            let (output, records) = self.open_output(path)?;
+            existing += records;
+            outputs.push(Mutex::new(BufWriter::new(output)));
+
+            if self.dedup && records > 0 {
+                let mut input = BufReader::new(File::open(path)?);
+                for _ in 0..records {
+                    let mut board = PackedBoard::zeroed();
+                    input.read_exact(bytemuck::bytes_of_mut(&mut board))?;
+                    seen.insert(board.unpack().unwrap().0.hash());
+                }
+            }
+        }
+        if existing > 0 {
+            println!("Resuming from {} existing positions", existing);
+        }
+
+        if existing >= self.positions {
+            return Ok(());
+        }
 
-        let game_counter = Arc::new(AtomicUsize::new(0));
-        let start = Instant::now();
+        let seen = Mutex::new(seen);
+
+        let game_counter = Arc::new(AtomicUsize::new(existing));
+        let generated = AtomicUsize::new(0);
+        let duplicates = AtomicUsize::new(0);
+        let next_shard = AtomicUsize::new(0);
+        let endings: [AtomicUsize; GameEnd::COUNT] = Default::default();
 
         opt.parallel(
+            self.positions - existing,
+            "positions",
             || Frozenight::new(64),
             |engine| {
-                let boards = self.play_game(engine, &tb);
+                let (mut boards, end) = self.play_game(engine, &tb);
+
+                generated.fetch_add(boards.len(), Ordering::Relaxed);
+                if self.dedup {
+                    let count = boards.len();
+                    let mut seen = seen.lock().unwrap();
+                    boards.retain(|board| seen.insert(board.unpack().unwrap().0.hash()));
+                    duplicates.fetch_add(count - boards.len(), Ordering::Relaxed);
+                }
 
                 let games = game_counter.fetch_add(boards.len(), Ordering::SeqCst);
                 if games >= self.positions {
                     return ControlFlow::Break(());
                 }
+                endings[end as usize].fetch_add(1, Ordering::Relaxed);
 
-                output
+                let shard = next_shard.fetch_add(1, Ordering::Relaxed) % outputs.len();
+                outputs[shard]
                     .lock()
                     .map(|mut output| output.write_all(bytemuck::cast_slice(&boards)))
                     .unwrap()
                     .unwrap();
 
-                let total = games + boards.len();
-                let completion = total as f64 / self.positions as f64;
-                let time = start.elapsed().as_secs_f64();
-                print!(
-                    "\r\x1b[K{:>6.2}% complete. {:.0} positions/sec. ETA: {}",
-                    completion * 100.0,
-                    total as f64 / time,
-                    eta(time, completion)
-                );
-                stdout().flush().unwrap();
-
-                ControlFlow::Continue(())
+                ControlFlow::Continue(boards.len())
+            },
+            || match self.dedup {
+                true => format!(
+                    "{:.2}% duplicates. ",
+                    duplicates.load(Ordering::Relaxed) as f64
+                        / generated.load(Ordering::Relaxed) as f64
+                        * 100.0
+                ),
+                false => String::new(),
             },
         );
-        println!();
+
+        let count = |end: GameEnd| endings[end as usize].load(Ordering::Relaxed);
+        println!(
+            "Games ended by: {} checkmate, {} repetition, {} insufficient material, \
+             {} stalemate or fifty-move rule, {} tablebase, {} win adjudication",
+            count(GameEnd::Checkmate),
+            count(GameEnd::Repetition),
+            count(GameEnd::InsufficientMaterial),
+            count(GameEnd::OtherDraw),
+            count(GameEnd::Tablebase),
+            count(GameEnd::WinAdjudication),
+        );
 
         Ok(())
     }
 
-    fn generate_starting_position(&self) -> Board {
-        let mut board = match () {
-            _ if self.frc => Board::chess960_startpos(thread_rng().gen_range(0..960)),
-            _ if self.dfrc => Board::double_chess960_startpos(
-                thread_rng().gen_range(0..960),
-                thread_rng().gen_range(0..960),
-            ),
-            _ => Board::default(),
-        };
-        for _ in 0..8 {
-            let mut moves = vec![];
-            board.generate_moves(|mvs| {
-                moves.extend(mvs);
-                false
-            });
-            if moves.is_empty() {
-                return self.generate_starting_position();
-            }
-            let mv = *moves.choose(&mut thread_rng()).unwrap();
-            board.play_unchecked(mv);
+    fn open_output(&self, path: &Path) -> std::io::Result<(File, usize)> {
+        if !self.resume {
+            let output = OpenOptions::new().create_new(true).write(true).open(path)?;
+            return Ok((output, 0));
         }
-        if board.status() != GameStatus::Ongoing {
-            return self.generate_starting_position();
-        }
-        board
+
+        let output = OpenOptions::new().create(true).append(true).open(path)?;
+        let record = std::mem::size_of::<PackedBoard>() as u64;
+        let records = output.metadata()?.len() / record;
+        // discard any partial record left behind by an interrupted run
+        output.set_len(records * record)?;
+        Ok((output, records as usize))
     }
 
-    fn play_game(&self, engine: &mut Frozenight, tb: &Tablebase) -> Vec<PackedBoard> {
-        let start_pos = self.generate_starting_position();
+    fn play_game(&self, engine: &mut Frozenight, tb: &Tablebase) -> (Vec<PackedBoard>, GameEnd) {
+        let start_pos = random_opening(self.frc, self.dfrc);
         let mut repetitions = HashSet::new();
         let mut game = vec![];
 
@@ -134,34 +193,34 @@ impl Options {
         });
 
         let mut outcome = None;
-        loop {
+        let mut tb_decided = false;
+        let mut adj_winner = None;
+        let mut adj_plies = 0;
+        let end = loop {
             match board.status() {
                 GameStatus::Won => {
                     outcome.get_or_insert(match board.side_to_move() {
                         Color::White => 0,
                         Color::Black => 2,
                     });
-                    break;
+                    break GameEnd::Checkmate;
                 }
                 GameStatus::Drawn => {
                     outcome.get_or_insert(match board.side_to_move() {
                         Color::White => 0,
                         Color::Black => 2,
                     });
-                    break;
+                    break GameEnd::OtherDraw;
                 }
                 GameStatus::Ongoing => {}
             }
 
-            if board.occupied().len() == 2
-                || board.occupied().len() == 3
-                    && !(board.pieces(Piece::Bishop) | board.pieces(Piece::Knight)).is_empty()
-            {
+            if insufficient_material(&board) {
                 outcome.get_or_insert(match board.side_to_move() {
                     Color::White => 0,
                     Color::Black => 2,
                 });
-                break;
+                break GameEnd::InsufficientMaterial;
             }
 
             if !repetitions.insert(board.hash()) {
@@ -169,30 +228,22 @@ impl Options {
                     Color::White => 0,
                     Color::Black => 2,
                 });
-                break;
+                break GameEnd::Repetition;
             }
 
-            let tb_outcome = match board.occupied().len() <= tb.max_pieces() {
-                true => match tb.probe_wdl(&board) {
-                    Some((Wdl::Win, _)) => Some(match board.side_to_move() {
-                        Color::White => 2,
-                        Color::Black => 0,
-                    }),
-                    Some((Wdl::Loss, _)) => Some(match board.side_to_move() {
-                        Color::White => 0,
-                        Color::Black => 2,
-                    }),
-                    Some(_) => Some(1),
-                    None => None,
-                },
-                false => None,
-            };
+            let tb_outcome = tb_outcome(&board, tb);
 
             if tb_outcome.is_some() && outcome.is_none() {
                 outcome = tb_outcome;
+                tb_decided = true;
             }
 
-            let mv = if thread_rng().gen_bool(self.random_move) {
+            let mv = if let Some(mv) = tb_outcome.and_then(|_| tb_move(&board, tb)) {
+                mv
+            } else if self.random_plies.map_or(true, |k| game.len() < k)
+                && board.checkers().is_empty()
+                && thread_rng().gen_bool(self.random_move)
+            {
                 let mut moves = vec![];
                 board.generate_moves(|mvs| {
                     moves.extend(mvs);
@@ -202,25 +253,59 @@ impl Options {
             } else {
                 engine.set_position(start_pos.clone(), game.iter().map(|&(mv, _)| mv));
 
-                engine
-                    .search(
-                        TimeConstraint {
-                            nodes: nodes_count.unwrap_or(u64::MAX),
-                            depth: self.depth.unwrap_or(250),
-                            ..TimeConstraint::INFINITE
-                        },
-                        |_| {},
-                    )
-                    .best_move
+                let info = engine.search(
+                    TimeConstraint {
+                        nodes: nodes_count.unwrap_or(u64::MAX),
+                        depth: self.depth.unwrap_or(250),
+                        ..TimeConstraint::INFINITE
+                    },
+                    |_| {},
+                );
+
+                if let Some(threshold) = self.win_adj_score {
+                    let winner = match info.eval.raw() as i32 {
+                        e if e >= threshold as i32 * Eval::CP_SCALE as i32 => {
+                            Some(board.side_to_move())
+                        }
+                        e if e <= -threshold as i32 * Eval::CP_SCALE as i32 => {
+                            Some(!board.side_to_move())
+                        }
+                        _ => None,
+                    };
+                    match winner {
+                        Some(_) if winner == adj_winner => adj_plies += 1,
+                        _ => adj_plies = 1,
+                    }
+                    adj_winner = winner;
+
+                    if let Some(winner) = winner {
+                        if adj_plies >= self.win_adj_moves {
+                            // TB outcomes take precedence if one was already found
+                            outcome.get_or_insert(match winner {
+                                Color::White => 2,
+                                Color::Black => 0,
+                            });
+                            break GameEnd::WinAdjudication;
+                        }
+                    }
+                }
+
+                info.best_move
             };
 
             game.push((mv, tb_outcome));
             board.play(mv);
-        }
+        };
 
         let outcome = outcome.unwrap();
+        // the game is played out after reaching the tablebase, but the tablebase decided it
+        let end = match tb_decided {
+            true => GameEnd::Tablebase,
+            false => end,
+        };
 
-        game.into_iter()
+        let boards = game
+            .into_iter()
             .scan(start_pos, |board, (mv, tb_outcome)| {
                 let value = PackedBoard::pack(&board, 0, tb_outcome.unwrap_or(outcome), 0);
                 let keep = board.checkers().is_empty();
@@ -228,6 +313,109 @@ impl Options {
                 Some((value, keep))
             })
             .filter_map(|(v, keep)| keep.then_some(v))
-            .collect()
+            .collect();
+        (boards, end)
+    }
+}
+
+/// How the outcome of a game was decided.
+#[derive(Clone, Copy)]
+enum GameEnd {
+    Checkmate,
+    Repetition,
+    InsufficientMaterial,
+    /// Stalemate or the fifty-move rule.
+    OtherDraw,
+    /// The game reached a tablebase position before any other ending.
+    Tablebase,
+    WinAdjudication,
+}
+
+impl GameEnd {
+    const COUNT: usize = 6;
+}
+
+/// Whether neither side has enough material left to checkmate: bare kings, or a lone minor piece.
+pub(crate) fn insufficient_material(board: &Board) -> bool {
+    board.occupied().len() == 2
+        || board.occupied().len() == 3
+            && !(board.pieces(Piece::Bishop) | board.pieces(Piece::Knight)).is_empty()
+}
+
+/// The tablebase result of `board` for white: 0 for a loss, 1 for a draw and 2 for a win, or
+/// `None` if the position is not in the tablebase.
+pub(crate) fn tb_outcome(board: &Board, tb: &Tablebase) -> Option<u8> {
+    if board.occupied().len() > tb.max_pieces() {
+        return None;
+    }
+    match tb.probe_wdl(board)? {
+        (Wdl::Win, _) => Some(match board.side_to_move() {
+            Color::White => 2,
+            Color::Black => 0,
+        }),
+        (Wdl::Loss, _) => Some(match board.side_to_move() {
+            Color::White => 0,
+            Color::Black => 2,
+        }),
+        _ => Some(1),
+    }
+}
+
+/// Plays 8 random plies from the standard, Chess960 or Double Chess960 starting position.
+pub(crate) fn random_opening(frc: bool, dfrc: bool) -> Board {
+    let mut board = match () {
+        _ if frc => Board::chess960_startpos(thread_rng().gen_range(0..960)),
+        _ if dfrc => Board::double_chess960_startpos(
+            thread_rng().gen_range(0..960),
+            thread_rng().gen_range(0..960),
+        ),
+        _ => Board::default(),
+    };
+    for _ in 0..8 {
+        let mut moves = vec![];
+        board.generate_moves(|mvs| {
+            moves.extend(mvs);
+            false
+        });
+        if moves.is_empty() {
+            return random_opening(frc, dfrc);
+        }
+        let mv = *moves.choose(&mut thread_rng()).unwrap();
+        board.play_unchecked(mv);
+    }
+    if board.status() != GameStatus::Ongoing {
+        return random_opening(frc, dfrc);
+    }
+    board
+}
+
+fn shard_path(path: &Path, shard: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{shard}"));
+    name.into()
+}
+
+/// Picks the DTZ-optimal move, or `None` if any resulting position could not be probed.
+fn tb_move(board: &Board, tb: &Tablebase) -> Option<Move> {
+    let mut moves = vec![];
+    board.generate_moves(|mvs| {
+        moves.extend(mvs);
+        false
+    });
+
+    let mut best = None;
+    for mv in moves {
+        let mut child = board.clone();
+        child.play_unchecked(mv);
+        // the child position is probed from the opponent's point of view
+        let key = match tb.probe_dtz(&child)? {
+            (Wdl::Loss, dtz) => (2, -(dtz as i64)),
+            (Wdl::Win, dtz) => (0, dtz as i64),
+            _ => (1, 0),
+        };
+        if best.map_or(true, |(k, _)| key > k) {
+            best = Some((key, mv));
+        }
     }
+    best.map(|(_, mv)| mv)
 }
@@ -0,0 +1,419 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Write};
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use cozy_chess::{Board, Color, GameStatus, Move};
+use cozy_syzygy::Tablebase;
+use frozenight::{Frozenight, TimeConstraint};
+use structopt::StructOpt;
+
+use crate::games::{insufficient_material, random_opening, tb_outcome};
+use crate::CommonOptions;
+
+/// The network is compiled into the engine, so to compare networks, build a UCI engine with each
+/// one and pass them as `--engine-a` and `--engine-b`.
+#[derive(StructOpt)]
+pub struct Options {
+    /// Number of games to play, rounded up to an even number so that each opening is played once
+    /// with each color
+    #[structopt(short = "g", long, parse(try_from_str = crate::parse_filter_underscore))]
+    games: usize,
+
+    /// UCI engine binary to play as engine A, instead of the engine built into gen-data
+    #[structopt(long)]
+    engine_a: Option<PathBuf>,
+    /// UCI engine binary to play as engine B, instead of the engine built into gen-data
+    #[structopt(long)]
+    engine_b: Option<PathBuf>,
+
+    /// Node limit of engine A
+    #[structopt(long)]
+    nodes_a: Option<u64>,
+    /// Depth limit of engine A
+    #[structopt(long)]
+    depth_a: Option<i16>,
+    /// Node limit of engine B
+    #[structopt(long)]
+    nodes_b: Option<u64>,
+    /// Depth limit of engine B
+    #[structopt(long)]
+    depth_b: Option<i16>,
+
+    /// Hash size of each engine in megabytes
+    #[structopt(long, default_value = "16")]
+    hash: usize,
+
+    #[structopt(long)]
+    frc: bool,
+    #[structopt(long, conflicts_with("frc"))]
+    dfrc: bool,
+
+    /// Elo difference of the SPRT null hypothesis
+    #[structopt(long, default_value = "0")]
+    elo0: f64,
+    /// Elo difference of the SPRT alternative hypothesis
+    #[structopt(long, default_value = "5")]
+    elo1: f64,
+}
+
+impl Options {
+    pub(super) fn run(self, opt: CommonOptions) -> std::io::Result<()> {
+        if self.nodes_a.is_none() && self.depth_a.is_none()
+            || self.nodes_b.is_none() && self.depth_b.is_none()
+        {
+            eprintln!("error: Each engine needs a node or depth limit");
+            std::process::exit(1);
+        }
+
+        let limits = [
+            limit(self.nodes_a, self.depth_a),
+            limit(self.nodes_b, self.depth_b),
+        ];
+        let tb = opt.syzygy();
+        let pairs = self.games / 2 + self.games % 2;
+        let started = AtomicUsize::new(0);
+        // indexed by engine A's result: loss, draw, win
+        let results: [AtomicUsize; 3] = Default::default();
+
+        opt.parallel(
+            pairs * 2,
+            "games",
+            || {
+                [&self.engine_a, &self.engine_b]
+                    .map(|path| or_exit(Player::new(path.as_deref(), self.hash)))
+            },
+            |engines| {
+                if started.fetch_add(1, Ordering::Relaxed) >= pairs {
+                    return ControlFlow::Break(());
+                }
+
+                let start = random_opening(self.frc, self.dfrc);
+                for a_color in [Color::White, Color::Black] {
+                    let white_result = or_exit(play_game(&start, engines, &limits, a_color, &tb));
+                    let a_result = match a_color {
+                        Color::White => white_result,
+                        Color::Black => 2 - white_result,
+                    };
+                    results[a_result as usize].fetch_add(1, Ordering::Relaxed);
+                }
+
+                ControlFlow::Continue(2)
+            },
+            || {
+                format!(
+                    "W-D-L: {}-{}-{}. ",
+                    results[2].load(Ordering::Relaxed),
+                    results[1].load(Ordering::Relaxed),
+                    results[0].load(Ordering::Relaxed),
+                )
+            },
+        );
+
+        let [losses, draws, wins] = [0, 1, 2].map(|i| results[i].load(Ordering::Relaxed));
+        if wins + draws + losses == 0 {
+            return Ok(());
+        }
+        let summary = Summary::new(wins, draws, losses);
+        println!(
+            "Engine A vs engine B: {}-{}-{} (W-D-L)",
+            wins, draws, losses
+        );
+        match summary.elo.is_finite() {
+            true => println!("Elo: {:.1} +/- {:.1}", summary.elo, summary.error),
+            // a clean sweep has no finite estimate
+            false => println!("Elo: {:+} (one engine scored every point)", summary.elo),
+        }
+        println!("LOS: {:.1}%", summary.los * 100.0);
+
+        let llr = summary.llr(self.elo0, self.elo1);
+        // the bounds for false positive and false negative rates of 5%
+        let bound = (0.95f64 / 0.05).ln();
+        let verdict = match () {
+            _ if llr >= bound => "H1 accepted",
+            _ if llr <= -bound => "H0 accepted",
+            _ => "inconclusive",
+        };
+        println!(
+            "SPRT [{}, {}]: LLR {:.2} ({:.2}, {:.2}), {}",
+            self.elo0, self.elo1, llr, -bound, bound, verdict
+        );
+
+        Ok(())
+    }
+}
+
+/// Depth limit for a side given only a node limit.
+const UNLIMITED_DEPTH: i16 = 250;
+
+fn limit(nodes: Option<u64>, depth: Option<i16>) -> TimeConstraint {
+    TimeConstraint {
+        nodes: nodes.unwrap_or(u64::MAX),
+        depth: depth.unwrap_or(UNLIMITED_DEPTH),
+        ..TimeConstraint::INFINITE
+    }
+}
+
+/// Reports a failed engine and stops the match, since its games can't be scored.
+fn or_exit<T>(result: std::io::Result<T>) -> T {
+    result.unwrap_or_else(|e| {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    })
+}
+
+/// Plays a game from `start` between engine A, playing `a_color`, and engine B. Returns the
+/// result for white: 0 for a loss, 1 for a draw and 2 for a win.
+fn play_game(
+    start: &Board,
+    engines: &mut [Player; 2],
+    limits: &[TimeConstraint; 2],
+    a_color: Color,
+    tb: &Tablebase,
+) -> std::io::Result<u8> {
+    for engine in &mut *engines {
+        engine.new_game()?;
+    }
+
+    let mut board = start.clone();
+    let mut moves = vec![];
+    let mut occurrences = HashMap::<_, u32>::new();
+    loop {
+        match board.status() {
+            GameStatus::Won => {
+                return Ok(match board.side_to_move() {
+                    Color::White => 0,
+                    Color::Black => 2,
+                })
+            }
+            GameStatus::Drawn => return Ok(1),
+            GameStatus::Ongoing => {}
+        }
+        let occurred = occurrences.entry(board.hash()).or_default();
+        *occurred += 1;
+        if insufficient_material(&board) || *occurred >= 3 {
+            return Ok(1);
+        }
+        if let Some(outcome) = tb_outcome(&board, tb) {
+            return Ok(outcome);
+        }
+
+        let side = match board.side_to_move() == a_color {
+            true => 0,
+            false => 1,
+        };
+        let mv = engines[side].best_move(start, &moves, &limits[side])?;
+
+        board.play(mv);
+        moves.push(mv);
+    }
+}
+
+/// One side of the match.
+enum Player {
+    /// The engine built into gen-data.
+    Builtin(Frozenight),
+    /// A UCI engine running in a child process.
+    Uci(UciEngine),
+}
+
+impl Player {
+    fn new(path: Option<&Path>, hash: usize) -> std::io::Result<Self> {
+        Ok(match path {
+            Some(path) => Player::Uci(UciEngine::start(path, hash)?),
+            None => Player::Builtin(Frozenight::new(hash)),
+        })
+    }
+
+    fn new_game(&mut self) -> std::io::Result<()> {
+        match self {
+            Player::Builtin(engine) => engine.new_game(),
+            Player::Uci(engine) => {
+                engine.send("ucinewgame")?;
+                engine.send("isready")?;
+                engine.wait_for("readyok")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The move the engine plays in the position after `moves` from `start`.
+    fn best_move(
+        &mut self,
+        start: &Board,
+        moves: &[Move],
+        limit: &TimeConstraint,
+    ) -> std::io::Result<Move> {
+        match self {
+            Player::Builtin(engine) => {
+                engine.set_position(start.clone(), moves.iter().copied());
+                Ok(engine.search(*limit, |_| {}).best_move)
+            }
+            Player::Uci(engine) => engine.best_move(start, moves, limit),
+        }
+    }
+}
+
+struct UciEngine {
+    process: Child,
+    input: ChildStdin,
+    output: BufReader<ChildStdout>,
+}
+
+impl UciEngine {
+    fn start(path: &Path, hash: usize) -> std::io::Result<Self> {
+        let mut process = Command::new(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let mut engine = UciEngine {
+            input: process.stdin.take().unwrap(),
+            output: BufReader::new(process.stdout.take().unwrap()),
+            process,
+        };
+        engine.send("uci")?;
+        engine.wait_for("uciok")?;
+        engine.send(&format!("setoption name Hash value {}", hash))?;
+        // moves are exchanged as the king moving onto its rook, which also covers Chess960
+        engine.send("setoption name UCI_Chess960 value true")?;
+        Ok(engine)
+    }
+
+    fn send(&mut self, command: &str) -> std::io::Result<()> {
+        writeln!(self.input, "{}", command)?;
+        self.input.flush()
+    }
+
+    /// Reads the engine's output up to the first line starting with `prefix`, and returns it.
+    fn wait_for(&mut self, prefix: &str) -> std::io::Result<String> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.output.read_line(&mut line)? == 0 {
+                return Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    format!("engine exited while waiting for {}", prefix),
+                ));
+            }
+            if line.starts_with(prefix) {
+                return Ok(line.trim_end().to_owned());
+            }
+        }
+    }
+
+    fn best_move(
+        &mut self,
+        start: &Board,
+        moves: &[Move],
+        limit: &TimeConstraint,
+    ) -> std::io::Result<Move> {
+        let mut position = format!("position fen {}", start);
+        let mut board = start.clone();
+        if !moves.is_empty() {
+            position += " moves";
+            for &mv in moves {
+                position += &format!(" {}", mv);
+                board.play_unchecked(mv);
+            }
+        }
+        self.send(&position)?;
+
+        let mut go = "go".to_owned();
+        if limit.nodes != u64::MAX {
+            go += &format!(" nodes {}", limit.nodes);
+        }
+        if limit.depth != UNLIMITED_DEPTH {
+            go += &format!(" depth {}", limit.depth);
+        }
+        self.send(&go)?;
+
+        let reply = self.wait_for("bestmove")?;
+        let mv = reply.split_ascii_whitespace().nth(1).unwrap_or("");
+        frozenight::parse_move(&board, mv, true).ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("engine played illegal move {:?} in {}", mv, board),
+            )
+        })
+    }
+}
+
+impl Drop for UciEngine {
+    fn drop(&mut self) {
+        let _ = self.send("quit");
+        let _ = self.process.wait();
+    }
+}
+
+/// Match statistics from engine A's point of view.
+struct Summary {
+    games: f64,
+    score: f64,
+    /// The variance of the result of a single game.
+    variance: f64,
+    elo: f64,
+    /// The half-width of the 95% confidence interval of `elo`.
+    error: f64,
+    /// The likelihood of superiority.
+    los: f64,
+}
+
+impl Summary {
+    fn new(wins: usize, draws: usize, losses: usize) -> Self {
+        let games = (wins + draws + losses) as f64;
+        let [w, d, l] = [wins, draws, losses].map(|n| n as f64 / games);
+        let score = w + d / 2.0;
+        let variance = w * (1.0 - score).powi(2) + d * (0.5 - score).powi(2) + l * score.powi(2);
+        let margin = 1.96 * (variance / games).sqrt();
+        let los = match wins + losses {
+            0 => 0.5,
+            decisive => {
+                let z = (wins as f64 - losses as f64) / (2.0 * decisive as f64).sqrt();
+                0.5 * (1.0 + erf(z))
+            }
+        };
+        // the interval has no finite Elo bound once it reaches a score of 0 or 1
+        let error = match score - margin > 0.0 && score + margin < 1.0 {
+            true => (score_to_elo(score + margin) - score_to_elo(score - margin)) / 2.0,
+            false => f64::INFINITY,
+        };
+        Summary {
+            games,
+            score,
+            variance,
+            elo: score_to_elo(score),
+            error,
+            los,
+        }
+    }
+
+    /// The log-likelihood ratio of the alternative hypothesis that the Elo difference is `elo1`
+    /// over the null hypothesis that it is `elo0`, using the normal approximation of the GSPRT.
+    fn llr(&self, elo0: f64, elo1: f64) -> f64 {
+        if self.variance == 0.0 {
+            return 0.0;
+        }
+        let s0 = elo_to_score(elo0);
+        let s1 = elo_to_score(elo1);
+        (s1 - s0) * (2.0 * self.score - s0 - s1) * self.games / (2.0 * self.variance)
+    }
+}
+
+fn score_to_elo(score: f64) -> f64 {
+    -400.0 * (1.0 / score - 1.0).log10()
+}
+
+fn elo_to_score(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+/// The error function, using the approximation 7.1.26 from Abramowitz and Stegun.
+fn erf(x: f64) -> f64 {
+    let t = 1.0 / (1.0 + 0.3275911 * x.abs());
+    let poly = t
+        * (0.254829592
+            + t * (-0.284496736 + t * (1.421413741 + t * (-1.453152027 + t * 1.061405429))));
+    (1.0 - poly * (-x * x).exp()).copysign(x)
+}
@@ -1,16 +1,21 @@
+use std::io::{stdout, Write};
 use std::num::NonZeroUsize;
 use std::ops::ControlFlow;
 use std::path::PathBuf;
 use std::str::FromStr;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
 use cozy_syzygy::Tablebase;
 use once_cell::sync::Lazy;
 use structopt::StructOpt;
 
 mod annotate;
+mod export;
 mod filter;
 mod games;
+mod gauntlet;
+mod shuffle;
 mod stats;
 
 static ABORT: AtomicBool = AtomicBool::new(false);
@@ -40,6 +45,13 @@ enum Subcommand {
     Annotate(annotate::Options),
     Stats(stats::Options),
     Filter(filter::Options),
+    /// Shuffle a dataset using bounded memory
+    Shuffle(shuffle::Options),
+    /// Write a dataset out as EPD text
+    Export(export::Options),
+    /// Play two search configurations against each other and estimate the Elo difference
+    #[structopt(name = "match")]
+    Match(gauntlet::Options),
 }
 
 fn main() {
@@ -55,6 +67,9 @@ fn main() {
         Subcommand::Annotate(opt) => opt.run(options.common),
         Subcommand::Stats(opt) => opt.run(options.common),
         Subcommand::Filter(opt) => opt.run(options.common),
+        Subcommand::Shuffle(opt) => opt.run(options.common),
+        Subcommand::Export(opt) => opt.run(options.common),
+        Subcommand::Match(opt) => opt.run(options.common),
     };
 
     if let Err(e) = r {
@@ -75,25 +90,85 @@ fn parse_filter_underscore<T: FromStr>(s: &str) -> Result<T, T::Err> {
 }
 
 impl CommonOptions {
+    /// Runs `f` on every worker thread until it breaks, while reporting progress once per second.
+    ///
+    /// `f` returns the number of items it processed; `total` is the expected number of items
+    /// overall, `unit` names them in the progress line, and `status` may add extra text to it.
     fn parallel<T>(
         &self,
+        total: usize,
+        unit: &str,
         init: impl Fn() -> T + Sync,
-        f: impl Fn(&mut T) -> ControlFlow<()> + Sync,
+        f: impl Fn(&mut T) -> ControlFlow<(), usize> + Sync,
+        status: impl Fn() -> String + Sync,
     ) {
+        let processed = AtomicUsize::new(0);
+        let finished = AtomicBool::new(false);
+        let start = Instant::now();
+
+        let print_progress = || {
+            let done = processed.load(Ordering::Relaxed);
+            let completion = done as f64 / total as f64;
+            let time = start.elapsed().as_secs_f64();
+            print!(
+                "\r\x1b[K{:>6.2}% complete. {:.0} {}/sec. {}ETA: {}",
+                completion * 100.0,
+                done as f64 / time,
+                unit,
+                status(),
+                eta(time, completion),
+            );
+            stdout().flush().unwrap();
+        };
+
         std::thread::scope(|s| {
-            for _ in 0..self.concurrency {
-                s.spawn(|| {
-                    let mut tl = init();
-                    while !ABORT.load(Ordering::Relaxed) {
-                        if f(&mut tl).is_break() {
-                            break;
-                        }
+            s.spawn(|| {
+                let mut next_print = Duration::from_secs(1);
+                while !finished.load(Ordering::Relaxed) {
+                    if start.elapsed() >= next_print {
+                        print_progress();
+                        next_print += Duration::from_secs(1);
                     }
-                });
+                    std::thread::sleep(Duration::from_millis(50));
+                }
+                print_progress();
+                println!();
+            });
+
+            let workers: Vec<_> = (0..self.concurrency)
+                .map(|_| {
+                    s.spawn(|| {
+                        let mut tl = init();
+                        while !ABORT.load(Ordering::Relaxed) {
+                            match f(&mut tl) {
+                                ControlFlow::Continue(n) => {
+                                    processed.fetch_add(n, Ordering::Relaxed);
+                                }
+                                ControlFlow::Break(()) => break,
+                            }
+                        }
+                    })
+                })
+                .collect();
+            let results: Vec<_> = workers.into_iter().map(|w| w.join()).collect();
+
+            finished.store(true, Ordering::Relaxed);
+            for result in results {
+                if let Err(e) = result {
+                    std::panic::resume_unwind(e);
+                }
             }
         });
     }
 
+    /// Limits the number of worker threads to the number of `chunk`-sized batches in `total`, so
+    /// small inputs don't start threads that would only contend for the reader.
+    fn for_chunks(mut self, total: usize, chunk: usize) -> Self {
+        let chunks = total.saturating_sub(1) / chunk + 1;
+        self.concurrency = self.concurrency.min(chunks);
+        self
+    }
+
     fn syzygy(&self) -> Tablebase {
         let mut tb = Tablebase::new();
         if let Some(path) = &self.syzygy {
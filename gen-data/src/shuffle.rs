@@ -0,0 +1,106 @@
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+use bytemuck::Zeroable;
+use marlinformat::PackedBoard;
+use rand::prelude::*;
+use structopt::StructOpt;
+
+use crate::CommonOptions;
+
+#[derive(StructOpt)]
+pub struct Options {
+    input: PathBuf,
+    output: PathBuf,
+
+    /// Maximum number of positions held in memory at once
+    #[structopt(
+        short = "c",
+        long,
+        default_value = "16_000_000",
+        parse(try_from_str = crate::parse_filter_underscore)
+    )]
+    chunk_size: usize,
+}
+
+impl Options {
+    pub(super) fn run(self, _: CommonOptions) -> std::io::Result<()> {
+        let record = std::mem::size_of::<PackedBoard>();
+        let input = File::open(&self.input)?;
+        let total = input.metadata()?.len() as usize / record;
+        let mut input = BufReader::new(input);
+
+        let mut output = BufWriter::new(
+            OpenOptions::new()
+                .create_new(true)
+                .write(true)
+                .open(&self.output)?,
+        );
+
+        // Scatter positions uniformly at random into temporary buckets small enough to be
+        // shuffled in memory, then shuffle each bucket and concatenate them.
+        let buckets = (total + self.chunk_size.max(1) - 1) / self.chunk_size.max(1);
+        let bucket_paths: Vec<_> = (0..buckets.max(1))
+            .map(|i| temp_path(&self.output, i))
+            .collect();
+        let mut bucket_files = bucket_paths
+            .iter()
+            .map(|path| {
+                OpenOptions::new()
+                    .create_new(true)
+                    .write(true)
+                    .open(path)
+                    .map(BufWriter::new)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut read = 0;
+        let mut board = PackedBoard::zeroed();
+        while input.read_exact(bytemuck::bytes_of_mut(&mut board)).is_ok() {
+            let bucket = bucket_files.choose_mut(&mut thread_rng()).unwrap();
+            bucket.write_all(bytemuck::bytes_of(&board))?;
+            read += 1;
+        }
+        for bucket in &mut bucket_files {
+            bucket.flush()?;
+        }
+        drop(bucket_files);
+
+        let mut written = 0;
+        let mut boards = Vec::new();
+        for path in &bucket_paths {
+            boards.clear();
+            let mut bucket = BufReader::new(File::open(path)?);
+            while bucket
+                .read_exact(bytemuck::bytes_of_mut(&mut board))
+                .is_ok()
+            {
+                boards.push(board);
+            }
+            boards.shuffle(&mut thread_rng());
+            output.write_all(bytemuck::cast_slice(&boards))?;
+            written += boards.len();
+            std::fs::remove_file(path)?;
+        }
+        output.flush()?;
+
+        if read != total || written != total {
+            return Err(Error::new(
+                ErrorKind::Other,
+                format!("expected {total} positions, read {read} and wrote {written}"),
+            ));
+        }
+
+        println!("Shuffled {written} positions");
+
+        Ok(())
+    }
+}
+
+fn temp_path(path: &Path, bucket: usize) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(format!(".tmp{bucket}"));
+    name.into()
+}
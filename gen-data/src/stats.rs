@@ -7,7 +7,7 @@ use std::{
 };
 
 use bytemuck::Zeroable;
-use cozy_chess::{Color, Piece};
+use frozenight::material_count;
 use marlinformat::PackedBoard;
 use structopt::StructOpt;
 
@@ -20,7 +20,10 @@ pub struct Options {
 
 impl Options {
     pub(super) fn run(self, opt: CommonOptions) -> std::io::Result<()> {
-        let input = Mutex::new(BufReader::new(File::open(self.dataset)?));
+        let input = File::open(self.dataset)?;
+        let total_positions = input.metadata()?.len() as usize / std::mem::size_of::<PackedBoard>();
+        let opt = opt.for_chunks(total_positions, 1024);
+        let input = Mutex::new(BufReader::new(input));
         let next = |boards: &mut Vec<_>| {
             let mut data = input.lock().unwrap();
             boards.clear();
@@ -35,6 +38,8 @@ impl Options {
         let full_stats = Mutex::new(Stats::default());
 
         opt.parallel(
+            total_positions,
+            "positions",
             || Vec::with_capacity(1024),
             |boards| {
                 next(boards);
@@ -43,22 +48,13 @@ impl Options {
                 }
                 let mut stats = Stats::default();
 
-                for board in boards {
-                    let (board, eval, _, _) = board.unpack().unwrap();
+                for board in &*boards {
+                    let (board, eval, outcome, _) = board.unpack().unwrap();
 
+                    // stored evals and outcomes are both from white's point of view
                     let wdl = 1.0 / (1.0 + (-eval as f64 / 1016.0).exp());
-                    let wdl = match board.side_to_move() {
-                        Color::White => wdl,
-                        Color::Black => 1.0 - wdl,
-                    };
 
-                    let material = board.pieces(Piece::Pawn).len() as usize
-                        + 3 * board.pieces(Piece::Bishop).len() as usize
-                        + 3 * board.pieces(Piece::Knight).len() as usize
-                        + 5 * board.pieces(Piece::Rook).len() as usize
-                        + 8 * board.pieces(Piece::Queen).len() as usize;
-
-                    let bucket = (material * 16 / 76).min(15);
+                    let bucket = (material_count(&board) * 16 / 76).min(15);
 
                     match eval {
                         0 => stats.draw_freq += 1,
@@ -67,6 +63,12 @@ impl Options {
                         _ => stats.eval_freq[(wdl * 32.0) as usize] += 1,
                     }
                     stats.buckets_freq[bucket] += 1;
+
+                    if !matches!(eval, i16::MIN..=-20000 | 20000..=i16::MAX) {
+                        let bin = ((wdl * 10.0) as usize).min(9);
+                        stats.calibration_count[bin] += 1;
+                        stats.calibration_points[bin] += outcome as u64;
+                    }
                 }
 
                 full_stats
@@ -80,14 +82,50 @@ impl Options {
                         }
                         full.mate_freq += stats.mate_freq;
                         full.draw_freq += stats.draw_freq;
+                        for (t, &n) in full
+                            .calibration_count
+                            .iter_mut()
+                            .zip(stats.calibration_count.iter())
+                        {
+                            *t += n;
+                        }
+                        for (t, &n) in full
+                            .calibration_points
+                            .iter_mut()
+                            .zip(stats.calibration_points.iter())
+                        {
+                            *t += n;
+                        }
                     })
                     .unwrap();
 
-                ControlFlow::Continue(())
+                ControlFlow::Continue(boards.len())
             },
+            String::new,
         );
 
-        dbg!(full_stats.into_inner().unwrap());
+        let full_stats = full_stats.into_inner().unwrap();
+        dbg!(full_stats);
+
+        println!("predicted  count        actual");
+        let bins = full_stats
+            .calibration_count
+            .iter()
+            .zip(full_stats.calibration_points.iter());
+        for (i, (&count, &points)) in bins.enumerate() {
+            // outcomes are stored in half points: 0 = loss, 1 = draw, 2 = win
+            let actual = match count {
+                0 => "-".to_string(),
+                _ => format!("{:.1}%", points as f64 / 2.0 / count as f64 * 100.0),
+            };
+            println!(
+                "{:>3}-{:>3}%  {:<11}  {:>6}",
+                i * 10,
+                i * 10 + 10,
+                count,
+                actual
+            );
+        }
 
         Ok(())
     }
@@ -99,4 +137,8 @@ struct Stats {
     eval_freq: [u64; 32],
     draw_freq: u64,
     mate_freq: u64,
+    /// Positions binned by the white win probability predicted from their eval
+    calibration_count: [u64; 10],
+    /// Sum of the white outcome in half points for each calibration bin
+    calibration_points: [u64; 10],
 }
@@ -4,6 +4,12 @@ fn main() {
     let hash = find_git_hash();
     let hash = hash.as_deref().unwrap_or("unknown");
     println!("cargo:rustc-env=GIT_HASH={}", hash);
+
+    // cargo only provides these to build scripts, so pass them on for the version report
+    for var in ["TARGET", "PROFILE"] {
+        let value = std::env::var(var).unwrap_or_else(|_| "unknown".to_string());
+        println!("cargo:rustc-env=BUILD_{}={}", var, value);
+    }
 }
 
 fn find_git_hash() -> Option<String> {
@@ -0,0 +1,94 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::str::FromStr;
+use std::sync::mpsc::channel;
+
+use frozenight::{MtFrozenight, TimeConstraint, INVALID_MOVE};
+
+use crate::{parse_fen, to_uci_castling};
+
+/// Searches each FEN in a file, one per line, and prints `fen;bestmove;eval;pv` for each.
+///
+/// Usage: `analyze <file> [--depth N] [--nodes N] [--threads N] [--hash MB]`
+pub fn analyze() {
+    let mut args = std::env::args().skip_while(|arg| arg != "analyze").skip(1);
+    let mut path = None;
+    let mut depth = None;
+    let mut nodes = None;
+    let mut threads = 1;
+    let mut hash_mb = 16;
+    while let Some(arg) = args.next() {
+        match &*arg {
+            "--depth" => depth = Some(value(&arg, args.next())),
+            "--nodes" => nodes = Some(value(&arg, args.next())),
+            "--threads" => threads = value(&arg, args.next()),
+            "--hash" => hash_mb = value(&arg, args.next()),
+            _ => path = Some(arg),
+        }
+    }
+    let path = path.unwrap_or_else(|| usage("no FEN file given"));
+    if depth.is_none() && nodes.is_none() {
+        usage("a --depth or --nodes limit is required");
+    }
+    let time = TimeConstraint {
+        depth: depth.unwrap_or(250),
+        nodes: nodes.unwrap_or(u64::MAX),
+        ..TimeConstraint::INFINITE
+    };
+
+    let file = File::open(&path).unwrap_or_else(|e| {
+        eprintln!("Failed to open {}: {}", path, e);
+        std::process::exit(1);
+    });
+    let mut engine = MtFrozenight::new(hash_mb);
+    engine.set_threads(threads);
+
+    for line in BufReader::new(file).lines() {
+        let line = line.unwrap_or_else(|e| {
+            eprintln!("Failed to read {}: {}", path, e);
+            std::process::exit(1);
+        });
+        let fen = line.trim();
+        if fen.is_empty() {
+            continue;
+        }
+        let board = match parse_fen(fen) {
+            Ok(board) => board,
+            Err(e) => {
+                eprintln!("Skipping invalid FEN {}: {:?}", fen, e);
+                continue;
+            }
+        };
+
+        engine.new_game();
+        engine.set_position(board.clone(), std::iter::empty());
+        let (send, recv) = channel();
+        engine.search(time, |_| {}, move |info| send.send(info.clone()).unwrap());
+        let info = recv.recv().unwrap();
+
+        let mut pv = vec![];
+        let mut pos = board.clone();
+        for &mv in &info.pv {
+            pv.push(to_uci_castling(&pos, mv, false).to_string());
+            pos.play(mv);
+        }
+        let best_move = match info.best_move == INVALID_MOVE {
+            // no legal moves
+            true => "0000".to_string(),
+            false => to_uci_castling(&board, info.best_move, false).to_string(),
+        };
+        println!("{};{};{};{}", fen, best_move, info.eval, pv.join(" "));
+    }
+}
+
+fn value<T: FromStr>(flag: &str, value: Option<String>) -> T {
+    value
+        .and_then(|v| v.parse().ok())
+        .unwrap_or_else(|| usage(&format!("{} needs a numeric value", flag)))
+}
+
+fn usage(error: &str) -> ! {
+    eprintln!("error: {}", error);
+    eprintln!("usage: analyze <file> [--depth N] [--nodes N] [--threads N] [--hash MB]");
+    std::process::exit(1);
+}
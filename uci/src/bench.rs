@@ -85,13 +85,7 @@ pub fn bench() {
         engine.set_position(pos.parse().unwrap(), std::iter::empty());
 
         let start = Instant::now();
-        let info = engine.search(
-            TimeConstraint {
-                depth,
-                ..TimeConstraint::INFINITE
-            },
-            |_| {},
-        );
+        let info = engine.search(TimeConstraint::fixed_depth(depth), |_| {});
         total_time += start.elapsed();
         total_nodes += info.nodes;
     }
@@ -0,0 +1,58 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use once_cell::sync::Lazy;
+
+/// The file set by the `DebugLogFile` option, if any.
+static LOG: Lazy<Mutex<Option<File>>> = Lazy::new(Default::default);
+
+/// Prints a line to the GUI, also writing it to the debug log if one is open.
+macro_rules! send {
+    ($($arg:tt)*) => {
+        $crate::log::send(&format!($($arg)*))
+    };
+}
+
+/// Starts appending to the log file at `path`, or stops logging if `path` is `None`.
+pub fn open(path: Option<&str>) -> std::io::Result<()> {
+    let file = match path {
+        Some(path) => Some(OpenOptions::new().create(true).append(true).open(path)?),
+        None => None,
+    };
+    *LOG.lock().unwrap() = file;
+    Ok(())
+}
+
+/// Records a command received from the GUI.
+pub fn received(command: &str) {
+    if let Some(file) = &mut *LOG.lock().unwrap() {
+        write(file, '<', command.trim_end());
+    }
+}
+
+pub fn send(line: &str) {
+    // print while holding the lock so that the log has lines in the same order as stdout
+    let mut log = LOG.lock().unwrap();
+    println!("{}", line);
+    if let Some(file) = &mut *log {
+        write(file, '>', line);
+    }
+}
+
+fn write(file: &mut File, direction: char, line: &str) {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    // flush every line so that the log survives a crash; a failing log shouldn't stop the engine
+    let _ = writeln!(
+        file,
+        "{}.{:03} {} {}",
+        time.as_secs(),
+        time.subsec_millis(),
+        direction,
+        line
+    )
+    .and_then(|_| file.flush());
+}
@@ -1,9 +1,14 @@
 use std::io::{stdin, stdout, Write};
+use std::str::FromStr;
 use std::time::{Duration, Instant};
 
-use cozy_chess::{Board, Color, File, GameStatus, Move, Piece, Square};
-use frozenight::{MtFrozenight, TimeConstraint};
+use cozy_chess::{Board, Color, File, GameStatus, Move, Square};
+use frozenight::{MtFrozenight, StopReason, TimeConstraint, INVALID_MOVE};
 
+#[macro_use]
+mod log;
+
+mod analyze;
 mod bench;
 
 fn main() {
@@ -11,12 +16,29 @@ fn main() {
         bench::bench();
         return;
     }
+    if std::env::args().any(|arg| arg == "analyze") {
+        analyze::analyze();
+        return;
+    }
 
     let mut frozenight = MtFrozenight::new(32);
+    let mut hash_mb = 32;
+    let mut max_memory_mb = 0;
+    // whether the memory estimate has changed since it was last reported at `isready`
+    let mut memory_changed = false;
 
     let mut move_overhead = Duration::from_millis(0);
     let mut ob_no_adj = false;
+    let mut adjudication_guard = 0;
+    let mut normalize_eval = false;
+    let mut white_relative_score = false;
+    let mut clear_hash_on_new_game = false;
+    // the UCI_Chess960 setting, and whether the current position uses 960 castling notation,
+    // which `position frc` and `position dfrc` also turn on
+    let mut uci_chess960 = false;
     let mut chess960 = false;
+    let mut debug = false;
+    let mut quit = false;
 
     let mut buf = String::new();
     loop {
@@ -30,25 +52,45 @@ fn main() {
             }
         }
         let now = Instant::now();
+        log::received(&buf);
         let mut stream = buf.split_ascii_whitespace().peekable();
 
         let _: Option<()> = (|| {
             match stream.next()? {
                 variant @ ("uci" | "ugi") => {
-                    println!(
-                        "id name Frozenight {} {}",
+                    send!(
+                        "id name Frozenight {} {} ({})",
+                        env!("CARGO_PKG_VERSION"),
+                        env!("GIT_HASH"),
+                        build_info()
+                    );
+                    send!("id author MinusKelvin <mark.carlson@minuskelvin.net>");
+                    send!(
+                        "option name UCI_EngineAbout type string default Frozenight {} {} by \
+                         MinusKelvin, built for {}",
                         env!("CARGO_PKG_VERSION"),
-                        env!("GIT_HASH")
+                        env!("GIT_HASH"),
+                        build_info()
                     );
-                    println!("id author MinusKelvin <mark.carlson@minuskelvin.net>");
-                    println!("option name Move Overhead type spin default 0 min 0 max 5000");
-                    println!("option name Hash type spin default 32 min 1 max 1048576");
-                    println!("option name Threads type spin default 1 min 1 max 64");
-                    println!("option name OB_noadj type check default false");
-                    println!("option name UCI_Chess960 type check default false");
+                    send!("option name Move Overhead type spin default 0 min 0 max 5000");
+                    send!("option name Hash type spin default 32 min 1 max 1048576");
+                    send!("option name Threads type spin default 1 min 1 max 64");
+                    send!("option name MaxMemory type spin default 0 min 0 max 1048576");
+                    send!("option name SaveHash type string default <empty>");
+                    send!("option name LoadHash type string default <empty>");
+                    send!("option name OB_noadj type check default false");
+                    send!("option name UCI_AdjudicationGuard type spin default 0 min 0 max 4000");
+                    send!("option name UCI_NormalizeEval type check default false");
+                    send!("option name UCI_WhiteRelativeScore type check default false");
+                    send!("option name UCI_ShowRefutations type check default false");
+                    send!("option name UCI_ClearHashOnNewGame type check default false");
+                    send!("option name UCI_Chess960 type check default false");
+                    #[cfg(feature = "thread-affinity")]
+                    send!("option name UCI_ThreadAffinity type check default false");
+                    send!("option name DebugLogFile type string default <empty>");
                     #[cfg(feature = "tweakable")]
                     for param in frozenight::all_parameters() {
-                        println!(
+                        send!(
                             "option name {} type spin default {} min {} max {}",
                             param.name(),
                             param.default,
@@ -56,41 +98,107 @@ fn main() {
                             param.max
                         );
                     }
-                    println!("{}ok", variant);
+                    send!("{}ok", variant);
                 }
                 "quit" => {
-                    std::process::exit(0);
+                    quit = true;
                 }
                 "isready" => {
-                    println!("readyok");
+                    if memory_changed {
+                        send!(
+                            "info string memory estimate {} MB",
+                            frozenight.memory_usage() / MB
+                        );
+                        memory_changed = false;
+                    }
+                    send!("readyok");
                 }
-                "setoption" => {
-                    stream.find(|&tok| tok == "name")?;
-                    let mut opt = String::new();
-                    while let Some(tok) = stream.next() {
-                        if tok == "value" {
-                            break;
-                        }
-                        if !opt.is_empty() {
-                            opt.push(' ');
+                #[cfg(feature = "tweakable")]
+                "spsa" => {
+                    for line in frozenight::dump_spsa_config().lines() {
+                        send!("{}", line);
+                    }
+                }
+                "debug" => match stream.next()? {
+                    #[cfg(feature = "trace")]
+                    "node" => {
+                        let fen = (&mut stream).collect::<Vec<_>>().join(" ");
+                        match parse_fen(&fen) {
+                            Ok(board) => {
+                                frozenight::trace_root(&board, TRACE_DEPTH);
+                            }
+                            Err(e) => send!("info string Invalid FEN: {:?}", e),
                         }
-                        opt.push_str(tok);
                     }
+                    value => debug = value == "on",
+                },
+                "setoption" => {
+                    let opt = parse_option_name(&mut stream)?;
                     match &*opt {
                         "Move Overhead" | "Move_Overhead" => {
-                            move_overhead = Duration::from_millis(stream.next()?.parse().ok()?)
+                            move_overhead = Duration::from_millis(parse_value(&opt, stream.next())?)
                         }
                         "Hash" => {
-                            frozenight.set_hash(stream.next()?.parse().ok()?);
+                            hash_mb = parse_value(&opt, stream.next())?;
+                            apply_hash(&mut frozenight, hash_mb, max_memory_mb);
+                            memory_changed = true;
+                        }
+                        "MaxMemory" => {
+                            max_memory_mb = parse_value(&opt, stream.next())?;
+                            apply_hash(&mut frozenight, hash_mb, max_memory_mb);
+                            memory_changed = true;
                         }
                         "OB_noadj" => {
                             ob_no_adj = stream.next()? == "true";
                         }
+                        "UCI_AdjudicationGuard" => {
+                            adjudication_guard = parse_value(&opt, stream.next())?;
+                        }
+                        "UCI_NormalizeEval" => {
+                            normalize_eval = stream.next()? == "true";
+                        }
+                        "UCI_WhiteRelativeScore" => {
+                            white_relative_score = stream.next()? == "true";
+                        }
+                        "UCI_ShowRefutations" => {
+                            frozenight.set_refutations(stream.next()? == "true");
+                        }
+                        "UCI_ClearHashOnNewGame" => {
+                            clear_hash_on_new_game = stream.next()? == "true";
+                        }
                         "UCI_Chess960" => {
-                            chess960 = stream.next()? == "true";
+                            uci_chess960 = stream.next()? == "true";
+                            chess960 = uci_chess960;
+                        }
+                        "UCI_ThreadAffinity" => {
+                            if !frozenight.set_thread_affinity(stream.next()? == "true") {
+                                send!("info string thread affinity is not supported here");
+                            }
+                        }
+                        "SaveHash" => {
+                            let path = option_string(&mut stream)?;
+                            if let Err(e) = frozenight.save_tt(&path) {
+                                send!("info string failed to save hash to {}: {}", path, e);
+                            }
+                        }
+                        "DebugLogFile" => {
+                            let path = option_string(&mut stream);
+                            if let Err(e) = log::open(path.as_deref()) {
+                                send!("info string failed to open log file: {}", e);
+                            }
+                        }
+                        "LoadHash" => {
+                            let path = option_string(&mut stream)?;
+                            if let Err(e) = frozenight.load_tt(&path) {
+                                send!("info string failed to load hash from {}: {}", path, e);
+                            }
                         }
                         "Threads" => {
-                            frozenight.set_threads(stream.next()?.parse().ok()?);
+                            frozenight.set_threads(parse_value(&opt, stream.next())?);
+                            if max_memory_mb != 0 {
+                                apply_hash(&mut frozenight, hash_mb, max_memory_mb);
+                            }
+                            memory_changed = true;
                         }
                         _ =>
                         {
@@ -99,27 +207,43 @@ fn main() {
                                 if opt != param.name() {
                                     continue;
                                 }
-                                param.set(stream.next()?.parse().ok()?);
+                                param.set(parse_value(&opt, stream.next())?);
                                 break;
                             }
                         }
                     }
                 }
                 "ucinewgame" | "uginewgame" => {
+                    if clear_hash_on_new_game {
+                        frozenight.clear_hash();
+                    }
                     frozenight.new_game();
                 }
                 "position" => {
-                    let mut board = match stream.next()? {
+                    let mut position_960 = uci_chess960;
+                    let board = match stream.next()? {
                         "startpos" => Board::default(),
+                        "frc" => {
+                            let id = parse_960_id(stream.next()?)?;
+                            position_960 = true;
+                            Board::chess960_startpos(id)
+                        }
+                        "dfrc" => {
+                            let white_id = parse_960_id(stream.next()?)?;
+                            let black_id = parse_960_id(stream.next()?)?;
+                            position_960 = true;
+                            Board::double_chess960_startpos(white_id, black_id)
+                        }
                         "fen" => {
-                            let fen_start = stream.next().unwrap().to_owned();
+                            let fen_start = stream.next()?.to_owned();
                             let fen = (&mut stream)
                                 .take_while(|&tok| tok != "moves")
                                 .fold(fen_start, |a, b| a + " " + b);
-                            match fen.parse() {
+                            // report to the GUI and keep the previous position
+                            match parse_fen(&fen) {
                                 Ok(b) => b,
                                 Err(e) => {
-                                    eprintln!("Invalid FEN: {:?}", e);
+                                    send!("info string Invalid FEN: {:?}", e);
                                     return None;
                                 }
                             }
@@ -131,27 +255,21 @@ fn main() {
                         stream.next();
                     }
 
-                    frozenight.set_position(
-                        board.clone(),
-                        std::iter::from_fn(|| {
-                            let mv =
-                                from_uci_castling(&board, stream.peek()?.parse().ok()?, chess960);
-                            stream.next();
-                            board.play(mv);
-                            Some(mv)
-                        }),
-                    );
+                    chess960 = position_960;
+                    let (start, moves) = parse_moves(board, &mut stream, chess960);
+                    frozenight.set_position(start, moves.into_iter());
                 }
                 "query" => match stream.next()? {
-                    "gameover" => println!(
+                    "gameover" => send!(
                         "response {}",
                         frozenight.board().status() != GameStatus::Ongoing
+                            || frozenight::is_dead_draw(frozenight.board())
                     ),
-                    "p1turn" => println!(
+                    "p1turn" => send!(
                         "response {}",
                         frozenight.board().side_to_move() == Color::White
                     ),
-                    "result" => println!(
+                    "result" => send!(
                         "response {}",
                         match frozenight.board().status() {
                             GameStatus::Won => match frozenight.board().side_to_move() {
@@ -159,94 +277,176 @@ fn main() {
                                 Color::Black => "p1win",
                             },
                             GameStatus::Drawn => "draw",
+                            // neither side can win, even though the rules let play continue
+                            _ if frozenight::is_dead_draw(frozenight.board()) => "draw",
                             GameStatus::Ongoing => "none",
                         }
                     ),
                     _ => {}
                 },
                 "go" => {
-                    let mut clock = None;
-                    let mut increment = Duration::ZERO;
-                    let mut use_all_time = true;
-                    let mut nodes = u64::MAX;
-                    let mut moves_to_go = None;
-
-                    let mut depth = 250;
+                    let GoParams {
+                        clocks,
+                        increments,
+                        movetime,
+                        nodes,
+                        moves_to_go,
+                        depth,
+                        wait_for_stop,
+                    } = parse_go(&mut stream);
 
                     let stm = frozenight.board().side_to_move();
-                    while let Some(param) = stream.next() {
-                        let mut grab_time = || {
-                            Duration::from_millis(
-                                stream.next().unwrap().parse::<i64>().unwrap().max(0) as u64,
-                            )
-                        };
-                        match param {
-                            "wtime" | "p1time" if stm == Color::White => {
-                                clock = Some(grab_time());
-                                use_all_time = false;
+                    let increment = increments[stm as usize];
+                    let (clock, use_all_time) = match (movetime, clocks[stm as usize]) {
+                        (Some(movetime), _) => (Some(movetime), true),
+                        (None, Some(clock)) => (Some(clock), false),
+                        (None, None) => match clocks[!stm as usize] {
+                            // a GUI that only sends the other side's clock would otherwise make
+                            // us think forever, so budget from that clock instead
+                            Some(clock) => {
+                                send!(
+                                    "info string no clock given for the side to move, \
+                                     budgeting from the other side's clock"
+                                );
+                                (Some(clock), false)
                             }
-                            "btime" | "p2time" if stm == Color::Black => {
-                                clock = Some(grab_time());
-                                use_all_time = false;
-                            }
-                            "winc" | "p1inc" if stm == Color::White => {
-                                increment = grab_time();
-                            }
-                            "binc" | "p2inc" if stm == Color::Black => {
-                                increment = grab_time();
-                            }
-                            "movetime" => {
-                                clock = Some(grab_time());
-                                use_all_time = true;
-                            }
-                            "movestogo" => {
-                                moves_to_go = Some(stream.next().unwrap().parse().unwrap())
-                            }
-                            "depth" => depth = stream.next().unwrap().parse().unwrap(),
-                            "nodes" => nodes = stream.next().unwrap().parse().unwrap(),
-                            _ => {}
-                        }
+                            None => (None, true),
+                        },
+                    };
+                    if debug {
+                        let show = |clock: Option<Duration>| {
+                            clock.map_or("none".to_string(), |c| c.as_millis().to_string())
+                        };
+                        send!(
+                            "info string clocks white={} black={}",
+                            show(clocks[Color::White as usize]),
+                            show(clocks[Color::Black as usize]),
+                        );
                     }
 
                     let board1 = frozenight.board().clone();
+                    let material = frozenight::material_count(&board1);
                     let board2 = frozenight.board().clone();
+                    let time = TimeConstraint {
+                        nodes,
+                        depth,
+                        clock,
+                        increment,
+                        overhead: move_overhead,
+                        moves_to_go,
+                        use_all_time,
+                        wait_for_stop,
+                    };
+                    let budget = time.budget();
+                    let score = move |eval| match white_relative_score {
+                        true => frozenight::white_relative(eval, stm),
+                        false => eval,
+                    };
+                    // stdout is line buffered, so each info line reaches the GUI as soon as it
+                    // is complete without flushing explicitly
                     frozenight.search(
-                        TimeConstraint {
-                            nodes,
-                            depth,
-                            clock,
-                            increment,
-                            overhead: move_overhead,
-                            moves_to_go,
-                            use_all_time,
-                        },
+                        time,
                         move |info| {
                             let time = now.elapsed();
-                            print!(
-                                "info depth {} seldepth {} nodes {} nps {} score {} time {} hashfull {} pv",
+                            let reported = match () {
+                                // report a constant +50 cp so OpenBench never adjudicates; this
+                                // overrides the adjudication guard
+                                _ if ob_no_adj => {
+                                    frozenight::Eval::new(50 * frozenight::Eval::CP_SCALE)
+                                        .to_string()
+                                }
+                                // cap the score so the GUI still sees who is better and by
+                                // roughly how much, but never enough to adjudicate. the cap is
+                                // at most the largest inconclusive score, so mates and tablebase
+                                // wins are always reported as the cap.
+                                _ if adjudication_guard > 0 => {
+                                    let guard = adjudication_guard;
+                                    let cp = match normalize_eval && !info.eval.is_conclusive() {
+                                        true => {
+                                            frozenight::normalize_eval(score(info.eval), material)
+                                        }
+                                        false => score(info.eval).as_centipawns(),
+                                    };
+                                    format!("cp {}", cp.clamp(-guard, guard))
+                                }
+                                _ if normalize_eval && !info.eval.is_conclusive() => format!(
+                                    "cp {}",
+                                    frozenight::normalize_eval(score(info.eval), material)
+                                ),
+                                _ => score(info.eval).to_string(),
+                            };
+                            let mut line = format!(
+                                "info depth {} seldepth {} nodes {} nps {} score {} time {} \
+                                 hashfull {} pv",
                                 info.depth,
                                 info.selective_depth,
                                 info.nodes,
                                 (info.nodes as f64 / time.as_secs_f64()).round() as u64,
-                                match ob_no_adj {
-                                    true => frozenight::Eval::new(250),
-                                    false => info.eval,
-                                },
+                                reported,
                                 time.as_millis(),
                                 info.hashfull,
                             );
                             let mut board = board1.clone();
                             for &mv in &info.pv {
-                                print!(" {}", to_uci_castling(&board, mv, chess960));
+                                line += &format!(" {}", to_uci_castling(&board, mv, chess960));
                                 board.play(mv);
                             }
-                            println!();
+                            send!("{}", line);
+                            if debug {
+                                send!(
+                                    "info string asp fh={} fl={} researches={}",
+                                    info.aspiration.fail_highs,
+                                    info.aspiration.fail_lows,
+                                    info.aspiration.researches,
+                                );
+                                let mut line = "info string nps/thread".to_string();
+                                for &nodes in &info.thread_nodes {
+                                    let nps = (nodes as f64 / time.as_secs_f64()).round() as u64;
+                                    line += &format!(" {}", nps);
+                                }
+                                send!("{}", line);
+                            }
                         },
                         move |info| {
-                            println!(
-                                "bestmove {}",
-                                to_uci_castling(&board2, info.best_move, chess960)
-                            );
+                            if let Some((soft, hard)) = budget.filter(|_| debug) {
+                                send!(
+                                    "info string time budget={} limit={} used={}",
+                                    soft.as_millis(),
+                                    hard.as_millis(),
+                                    now.elapsed().as_millis()
+                                );
+                            }
+                            match info.stop_reason {
+                                Some(StopReason::Nodes) => {
+                                    send!("info string stopped: node limit")
+                                }
+                                Some(StopReason::Time) => send!("info string stopped: time"),
+                                Some(StopReason::Abort) => send!("info string stopped: stop"),
+                                _ => {}
+                            }
+                            for (mv, line) in &info.refutations {
+                                let mut board = board2.clone();
+                                let mut text = format!(
+                                    "info refutation {}",
+                                    to_uci_castling(&board, *mv, chess960)
+                                );
+                                board.play(*mv);
+                                for &mv in line {
+                                    text += &format!(" {}", to_uci_castling(&board, mv, chess960));
+                                    board.play(mv);
+                                }
+                                send!("{}", text);
+                            }
+                            if info.best_move == INVALID_MOVE {
+                                // no legal moves; report the terminal score and a null move
+                                send!("info depth 0 score {}", score(info.eval));
+                                send!("bestmove 0000");
+                            } else {
+                                send!(
+                                    "bestmove {}",
+                                    to_uci_castling(&board2, info.best_move, chess960)
+                                );
+                            }
                             stdout().flush().unwrap();
                         },
                     );
@@ -258,13 +458,83 @@ fn main() {
             }
             None
         })();
+
+        if quit {
+            // let a running search report its best move before the threads are joined
+            frozenight.shutdown();
+            return;
+        }
+    }
+}
+
+const MB: usize = 1024 * 1024;
+
+/// The depth searched by `debug node`, shallow enough to keep the trace readable.
+#[cfg(feature = "trace")]
+const TRACE_DEPTH: i16 = 6;
+
+/// Resizes the hash table to `hash_mb`, shrinking it if needed so that the estimated total memory
+/// use stays within `max_memory_mb`. A limit of 0 means no limit.
+fn apply_hash(frozenight: &mut MtFrozenight, hash_mb: usize, max_memory_mb: usize) {
+    let mut size = hash_mb;
+    if max_memory_mb != 0 {
+        // round up, as this is only an estimate
+        let threads_mb = frozenight.thread_memory_usage() / MB + 1;
+        size = size.min(max_memory_mb.saturating_sub(threads_mb)).max(1);
+        if size < hash_mb {
+            send!(
+                "info string hash reduced to {} MB to fit MaxMemory {} MB",
+                size,
+                max_memory_mb
+            );
+        }
+    }
+    frozenight.set_hash(size);
+}
+
+/// Plays the moves of a `position` command on `board`, returning the position the move history
+/// starts from and the moves played since. Parsing stops at the first illegal move.
+fn parse_moves<'a>(
+    mut board: Board,
+    tokens: impl Iterator<Item = &'a str>,
+    chess960: bool,
+) -> (Board, Vec<Move>) {
+    let mut start = board.clone();
+    let mut moves = vec![];
+    for tok in tokens {
+        if tok == "0000" {
+            // a null move can't be part of the move history, so the history starts over from
+            // the position after it
+            match board.null_move() {
+                Some(after) => board = after,
+                None => {
+                    send!("info string ignoring null move while in check");
+                    break;
+                }
+            }
+            start = board.clone();
+            moves.clear();
+            continue;
+        }
+        let mv = match frozenight::parse_move(&board, tok, chess960) {
+            Some(mv) => mv,
+            None => {
+                send!("info string ignoring illegal move {}", tok);
+                break;
+            }
+        };
+        board.play(mv);
+        moves.push(mv);
     }
+    (start, moves)
 }
 
 fn to_uci_castling(board: &Board, mut mv: Move, chess960: bool) -> Move {
     if chess960 {
         return mv;
     }
+    // castling is the only move onto a square occupied by the same color; this can't match a
+    // promotion, since its destination is always empty or an enemy piece
     if board.color_on(mv.from) == board.color_on(mv.to) {
         if mv.to.file() > mv.from.file() {
             mv.to = Square::new(File::G, mv.to.rank());
@@ -275,16 +545,283 @@ fn to_uci_castling(board: &Board, mut mv: Move, chess960: bool) -> Move {
     mv
 }
 
-fn from_uci_castling(board: &Board, mut mv: Move, chess960: bool) -> Move {
-    if chess960 {
-        return mv;
+/// The limits given by a `go` command. Clocks and increments are indexed by color.
+struct GoParams {
+    clocks: [Option<Duration>; Color::NUM],
+    increments: [Duration; Color::NUM],
+    movetime: Option<Duration>,
+    nodes: u64,
+    moves_to_go: Option<u32>,
+    depth: i16,
+    wait_for_stop: bool,
+}
+
+/// Parses the parameters of a `go` command. Malformed values are reported and ignored, as are
+/// unknown parameters.
+fn parse_go<'a>(mut stream: impl Iterator<Item = &'a str>) -> GoParams {
+    let mut go = GoParams {
+        clocks: [None; Color::NUM],
+        increments: [Duration::ZERO; Color::NUM],
+        movetime: None,
+        nodes: u64::MAX,
+        moves_to_go: None,
+        depth: 250,
+        wait_for_stop: false,
+    };
+
+    while let Some(param) = stream.next() {
+        let mut grab_time = || {
+            parse_value::<i64>(param, stream.next())
+                .map(|ms| Duration::from_millis(ms.max(0) as u64))
+        };
+        let white = Color::White as usize;
+        let black = Color::Black as usize;
+        match param {
+            "wtime" | "p1time" => go.clocks[white] = grab_time().or(go.clocks[white]),
+            "btime" | "p2time" => go.clocks[black] = grab_time().or(go.clocks[black]),
+            "winc" | "p1inc" => go.increments[white] = grab_time().unwrap_or(go.increments[white]),
+            "binc" | "p2inc" => go.increments[black] = grab_time().unwrap_or(go.increments[black]),
+            "movetime" => go.movetime = grab_time().or(go.movetime),
+            "movestogo" => go.moves_to_go = parse_value(param, stream.next()).or(go.moves_to_go),
+            "depth" => go.depth = parse_value(param, stream.next()).unwrap_or(go.depth),
+            "nodes" => go.nodes = parse_value(param, stream.next()).unwrap_or(go.nodes),
+            "infinite" => go.wait_for_stop = true,
+            _ => {}
+        }
     }
-    if mv.from.file() == File::E && board.piece_on(mv.from) == Some(Piece::King) {
-        if mv.to.file() == File::G {
-            mv.to = Square::new(File::H, mv.to.rank());
-        } else if mv.to.file() == File::C {
-            mv.to = Square::new(File::A, mv.to.rank());
+    go
+}
+
+/// Parses the name of a `setoption` command, leaving `stream` at the start of the value. Returns
+/// `None` if there is no name.
+fn parse_option_name<'a>(stream: &mut impl Iterator<Item = &'a str>) -> Option<String> {
+    stream.find(|&tok| tok == "name")?;
+    let mut opt = String::new();
+    for tok in stream {
+        if tok == "value" {
+            break;
+        }
+        if !opt.is_empty() {
+            opt.push(' ');
         }
+        opt.push_str(tok);
+    }
+    match opt.is_empty() {
+        true => None,
+        false => Some(opt),
+    }
+}
+
+/// Parses a FEN, filling in the halfmove clock and fullmove number if they are missing.
+fn parse_fen(fen: &str) -> Result<Board, <Board as FromStr>::Err> {
+    let fields = fen.split_ascii_whitespace().count();
+    let mut fen = fen.to_owned();
+    for default in [" 0", " 1"].iter().skip(fields.saturating_sub(4)) {
+        fen += default;
+    }
+    fen.parse()
+}
+
+/// Describes how this binary was built, so that bug reports can identify the exact build.
+fn build_info() -> String {
+    let mut info = format!("{} {}", env!("BUILD_TARGET"), env!("BUILD_PROFILE"));
+    if cfg!(target_feature = "avx2") {
+        info += " avx2";
+    }
+    if cfg!(feature = "tweakable") {
+        info += " tweakable";
+    }
+    if cfg!(feature = "crelu") {
+        info += " crelu";
+    }
+    if cfg!(feature = "no-nnue") {
+        info += " no-nnue";
+    }
+    if cfg!(feature = "trace") {
+        info += " trace";
+    }
+    if cfg!(feature = "thread-affinity") {
+        info += " thread-affinity";
+    }
+    info
+}
+
+/// Parses the value of a `go` parameter or option, reporting it to the GUI if it is malformed.
+fn parse_value<T: FromStr>(name: &str, value: Option<&str>) -> Option<T> {
+    let parsed = value.and_then(|v| v.parse().ok());
+    if parsed.is_none() {
+        send!(
+            "info string ignoring invalid value for {}: {}",
+            name,
+            value.unwrap_or("")
+        );
+    }
+    parsed
+}
+
+/// Collects the rest of the command as a string option value, or `None` if it is empty.
+fn option_string<'a>(stream: impl Iterator<Item = &'a str>) -> Option<String> {
+    let value = stream.collect::<Vec<_>>().join(" ");
+    match &*value {
+        "" | "<empty>" => None,
+        _ => Some(value),
+    }
+}
+
+fn parse_960_id(id: &str) -> Option<u32> {
+    match id.parse() {
+        Ok(id) if id < 960 => Some(id),
+        _ => {
+            send!("info string Invalid Chess960 position id: {}", id);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cozy_chess::{BitBoard, Piece};
+
+    use super::*;
+
+    #[test]
+    fn garbage_go_is_ignored() {
+        let lines = [
+            "wtime abc btime -5 winc 1.5 binc",
+            "movetime",
+            "depth 99999 nodes -1 movestogo x",
+            "infinite depth",
+            "ponder searchmoves e2e4 wtime",
+            "",
+        ];
+        for line in lines {
+            parse_go(line.split_ascii_whitespace());
+        }
+
+        let go = parse_go("wtime abc btime -5 winc 100 binc x depth q".split_ascii_whitespace());
+        assert_eq!(go.clocks, [None, Some(Duration::ZERO)]);
+        assert_eq!(go.increments, [Duration::from_millis(100), Duration::ZERO]);
+        assert_eq!(go.depth, 250);
+        assert_eq!(go.nodes, u64::MAX);
+
+        let go = parse_go("movetime 50 nodes 1000 movestogo 7".split_ascii_whitespace());
+        assert_eq!(go.movetime, Some(Duration::from_millis(50)));
+        assert_eq!(go.nodes, 1000);
+        assert_eq!(go.moves_to_go, Some(7));
+        assert!(!go.wait_for_stop);
+
+        let go = parse_go("infinite".split_ascii_whitespace());
+        assert!(go.wait_for_stop);
+        assert_eq!(go.depth, 250);
+    }
+
+    #[test]
+    fn garbage_setoption_is_ignored() {
+        for line in ["", "value 5", "name", "name value 5", "Hash 5"] {
+            assert_eq!(parse_option_name(&mut line.split_ascii_whitespace()), None);
+        }
+
+        let mut stream = "name Move Overhead value abc".split_ascii_whitespace();
+        assert_eq!(parse_option_name(&mut stream).unwrap(), "Move Overhead");
+        assert_eq!(parse_value::<u64>("Move Overhead", stream.next()), None);
+
+        let mut stream = "junk name Hash value".split_ascii_whitespace();
+        assert_eq!(parse_option_name(&mut stream).unwrap(), "Hash");
+        assert_eq!(parse_value::<usize>("Hash", stream.next()), None);
+    }
+
+    #[test]
+    fn missing_fen_counters_are_defaulted() {
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq -";
+        assert_eq!(
+            parse_fen(start).unwrap().to_string(),
+            start.to_owned() + " 0 1"
+        );
+        assert_eq!(
+            parse_fen(&(start.to_owned() + " 3")).unwrap().to_string(),
+            start.to_owned() + " 3 1"
+        );
+        assert_eq!(
+            parse_fen(&(start.to_owned() + " 3 9")).unwrap().to_string(),
+            start.to_owned() + " 3 9"
+        );
+    }
+
+    #[test]
+    fn malformed_fens_are_rejected() {
+        let fens = [
+            "",
+            "garbage",
+            "not a fen at all",
+            // too few and too many ranks
+            "rnbqkbnr/pppppppp/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "rnbqkbnr/pppppppp/8/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            // too few and too many files in a rank
+            "rnbqkbnr/pppppppp/7/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "rnbqkbnr/pppppppp/9/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            // missing side to move or castling rights
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR x KQkq - 0 1",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - x 1",
+        ];
+        for fen in fens {
+            assert!(parse_fen(fen).is_err(), "{}", fen);
+        }
+    }
+
+    fn play(start: &Board, moves: &[Move]) -> Board {
+        let mut board = start.clone();
+        for &mv in moves {
+            board.play(mv);
+        }
+        board
+    }
+
+    #[test]
+    fn underpromotions_are_played() {
+        let board: Board = "k7/4P3/8/8/8/8/8/4K3 w - - 0 1".parse().unwrap();
+        for (tok, piece) in [
+            ("e7e8q", Piece::Queen),
+            ("e7e8r", Piece::Rook),
+            ("e7e8b", Piece::Bishop),
+            ("e7e8n", Piece::Knight),
+        ] {
+            let (start, moves) = parse_moves(board.clone(), std::iter::once(tok), false);
+            assert_eq!(moves.len(), 1, "{}", tok);
+            assert_eq!(moves[0].promotion, Some(piece));
+            let after = play(&start, &moves);
+            assert_eq!(after.piece_on(Square::E8), Some(piece));
+            assert_eq!(after.pieces(Piece::Pawn), BitBoard::EMPTY);
+        }
+    }
+
+    #[test]
+    fn promotions_are_not_mistaken_for_castling() {
+        let board: Board = "r3k2r/1P6/8/8/8/8/8/R3K2R w KQkq - 0 1".parse().unwrap();
+        for tok in ["b7b8q", "b7a8n", "b7b8r"] {
+            let mv: Move = tok.parse().unwrap();
+            assert!(board.is_legal(mv));
+            assert_eq!(to_uci_castling(&board, mv, false), mv);
+            assert_eq!(to_uci_castling(&board, mv, true), mv);
+        }
+        let castle: Move = "e1h1".parse().unwrap();
+        assert_eq!(to_uci_castling(&board, castle, false).to_string(), "e1g1");
+    }
+
+    #[test]
+    fn null_moves_restart_the_history() {
+        let tokens = "e2e4 0000 d2d4 e7e5".split_ascii_whitespace();
+        let (start, moves) = parse_moves(Board::default(), tokens, false);
+        let after_e4 = play(&Board::default(), &["e2e4".parse().unwrap()]);
+        assert!(start.same_position(&after_e4.null_move().unwrap()));
+        let moves: Vec<_> = moves.iter().map(|mv| mv.to_string()).collect();
+        assert_eq!(moves, ["d2d4", "e7e5"]);
+
+        // a null move in check is illegal, so it ends the moves like any other
+        let tokens = "e2e4 f7f6 d1h5 0000 g7g6".split_ascii_whitespace();
+        let (start, moves) = parse_moves(Board::default(), tokens, false);
+        assert!(start.same_position(&Board::default()));
+        assert_eq!(moves.len(), 3);
     }
-    mv
 }